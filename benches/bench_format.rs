@@ -19,25 +19,28 @@ use _lib::message::{Message, MessageType};
 
 fn format(c: &mut Criterion) {
     let mut group = c.benchmark_group("format");
-    for escapes in [false, true] {
-        let arg_value = if escapes {
-            b"[1, 2, 3, 4, 5, 6, 7, 8]".as_slice()
-        } else {
-            b"123.4567890:123.45678901".as_slice()
-        };
+    for (name, arg_value) in [
+        ("no escapes", b"123.4567890:123.45678901".as_slice()),
+        ("escapes", b"[1, 2, 3, 4, 5, 6, 7, 8]".as_slice()),
+        // Worst case: every byte needs an escape
+        ("all escapes", [b'\\'; 24].as_slice()),
+        ("long", [b'x'; 1000].as_slice()),
+    ] {
         for args in [1, 10, 100, 1000, 10000] {
             let msg: Message<&[u8], &[u8]> = Message::new(
                 MessageType::Request,
-                b"test_message".as_slice(),
+                b"test-message".as_slice(),
                 Some(12345678),
                 vec![arg_value; args],
             );
             let len = msg.to_vec().len();
-            let name = if escapes { "escapes" } else { "no escapes" };
             group.throughput(Throughput::Bytes(len as u64));
             group.bench_function(BenchmarkId::new(name, args), |b| {
                 b.iter_with_large_drop(|| msg.to_vec());
             });
+            group.bench_function(BenchmarkId::new(format!("{name} single pass"), args), |b| {
+                b.iter_with_large_drop(|| msg.to_vec_single_pass());
+            });
         }
     }
     group.finish();
@@ -13,7 +13,7 @@
  * limitations under the License.
  */
 
-use enum_map::{enum_map, EnumMap};
+use enum_map::{enum_map, Enum, EnumMap};
 use std::collections::HashMap;
 use std::error::Error;
 use std::fs;
@@ -21,7 +21,7 @@ use std::io::Write;
 use std::path::Path;
 use std::rc::Rc;
 
-use katcp_codec_fsm::{Action, MessageType, State};
+use katcp_codec_fsm::{Action, KatcpVersion, MessageType, State};
 
 /// (state, char) entry in the state machine.
 ///
@@ -120,11 +120,16 @@ fn make_before_name() -> EnumMap<u8, Entry> {
 }
 
 /// Create the transition table for [State::Name].
-fn make_name() -> EnumMap<u8, Entry> {
+///
+/// Version 4 has no message IDs, so the characters that would start one are
+/// errors.
+fn make_name(version: KatcpVersion) -> EnumMap<u8, Entry> {
+    let has_id = version != KatcpVersion::V4;
     make_table(|ch| match ch {
         b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' => Entry::new(Action::Name, State::Name),
-        b' ' => Entry::new(Action::Nothing, State::BeforeArgument),
-        b'[' => Entry::new(Action::Nothing, State::BeforeId),
+        b' ' => Entry::new(Action::Separator, State::BeforeArgument),
+        b'[' if has_id => Entry::new(Action::StartId, State::BeforeId),
+        b':' if has_id => Entry::new(Action::ColonId, State::BeforeColonId),
         b'\n' => Entry::new(Action::Nothing, State::EndOfLine),
         _ => Entry::error(),
     })
@@ -133,7 +138,8 @@ fn make_name() -> EnumMap<u8, Entry> {
 /// Create the transition table for [State::BeforeId].
 fn make_before_id() -> EnumMap<u8, Entry> {
     make_table(|ch| match ch {
-        b'1'..=b'9' => Entry::new(Action::Id, State::Id),
+        // A leading zero is rejected at runtime unless enabled
+        b'0'..=b'9' => Entry::new(Action::Id, State::Id),
         _ => Entry::error(),
     })
 }
@@ -142,7 +148,7 @@ fn make_before_id() -> EnumMap<u8, Entry> {
 fn make_id() -> EnumMap<u8, Entry> {
     make_table(|ch| match ch {
         b'0'..=b'9' => Entry::new(Action::Id, State::Id),
-        b']' => Entry::new(Action::Nothing, State::AfterId),
+        b']' => Entry::new(Action::EndId, State::AfterId),
         _ => Entry::error(),
     })
 }
@@ -150,7 +156,25 @@ fn make_id() -> EnumMap<u8, Entry> {
 /// Create the transition table for [State::AfterId].
 fn make_after_id() -> EnumMap<u8, Entry> {
     make_table(|ch| match ch {
-        b' ' => Entry::new(Action::Nothing, State::BeforeArgument),
+        b' ' => Entry::new(Action::Separator, State::BeforeArgument),
+        b'\n' => Entry::new(Action::Nothing, State::EndOfLine),
+        _ => Entry::error(),
+    })
+}
+
+/// Create the transition table for [State::BeforeColonId].
+fn make_before_colon_id() -> EnumMap<u8, Entry> {
+    make_table(|ch| match ch {
+        b'1'..=b'9' => Entry::new(Action::Id, State::ColonId),
+        _ => Entry::error(),
+    })
+}
+
+/// Create the transition table for [State::ColonId].
+fn make_colon_id() -> EnumMap<u8, Entry> {
+    make_table(|ch| match ch {
+        b'0'..=b'9' => Entry::new(Action::Id, State::ColonId),
+        b' ' => Entry::new(Action::Separator, State::BeforeArgument),
         b'\n' => Entry::new(Action::Nothing, State::EndOfLine),
         _ => Entry::error(),
     })
@@ -161,8 +185,13 @@ fn make_after_id() -> EnumMap<u8, Entry> {
 /// If `create_argument` is true, a non-space character will start a new
 /// argument. This should be done for [State::BeforeArgument].
 fn make_argument(create_argument: bool) -> EnumMap<u8, Entry> {
+    let separator = if create_argument {
+        Action::ExtraSeparator
+    } else {
+        Action::Separator
+    };
     make_table(|ch| match ch {
-        b' ' => Entry::new(Action::Nothing, State::BeforeArgument),
+        b' ' => Entry::new(separator.clone(), State::BeforeArgument),
         b'\n' => Entry::new(Action::Nothing, State::EndOfLine),
         b'\\' => Entry::new_full(Action::Nothing, State::ArgumentEscape, create_argument),
         b'\0' | b'\x1B' => Entry::error(),
@@ -170,6 +199,14 @@ fn make_argument(create_argument: bool) -> EnumMap<u8, Entry> {
     })
 }
 
+/// Create the transition table for [State::Skip].
+fn make_skip() -> EnumMap<u8, Entry> {
+    make_table(|ch| match ch {
+        b'\n' => Entry::new(Action::EndSkip, State::Start),
+        _ => Entry::new(Action::Nothing, State::Skip),
+    })
+}
+
 /// Create the transition table for [State::ArgumentEscape].
 fn make_argument_escape() -> EnumMap<u8, Entry> {
     make_table(|ch| match ch {
@@ -225,19 +262,40 @@ fn build_fast_tables(table: &mut EnumMap<State, EnumMap<u8, Entry>>) {
     }
 }
 
-/// Build the parser table.
-fn parser_table() -> EnumMap<State, EnumMap<u8, Entry>> {
+/// Maximum number of ranges of rejected characters for which a fast table
+/// is scanned with SIMD (each range costs a few instructions per vector).
+const MAX_REJECT_RANGES: usize = 8;
+
+/// Get the maximal inclusive ranges of characters rejected by a fast table.
+fn reject_ranges(fast_table: &EnumMap<u8, bool>) -> Vec<(u8, u8)> {
+    let mut ranges: Vec<(u8, u8)> = vec![];
+    for ch in 0..=255u8 {
+        if !fast_table[ch] {
+            match ranges.last_mut() {
+                Some(last) if last.1 + 1 == ch => last.1 = ch,
+                _ => ranges.push((ch, ch)),
+            }
+        }
+    }
+    ranges
+}
+
+/// Build the parser table for a protocol version.
+fn parser_table(version: KatcpVersion) -> EnumMap<State, EnumMap<u8, Entry>> {
     let mut table = enum_map! {
         State::Start => make_start(),
         State::Empty => make_empty(),
         State::BeforeName => make_before_name(),
-        State::Name => make_name(),
+        State::Name => make_name(version),
         State::BeforeId => make_before_id(),
         State::Id => make_id(),
         State::AfterId => make_after_id(),
+        State::BeforeColonId => make_before_colon_id(),
+        State::ColonId => make_colon_id(),
         State::BeforeArgument => make_argument(true),
         State::Argument => make_argument(false),
         State::ArgumentEscape => make_argument_escape(),
+        State::Skip => make_skip(),
         State::Error => make_error(),
         State::EndOfLine => make_error(),
         State::ErrorEndOfLine => make_error(),
@@ -246,13 +304,21 @@ fn parser_table() -> EnumMap<State, EnumMap<u8, Entry>> {
     table
 }
 
-fn write_parser_tables(w: &mut impl Write) -> Result<(), std::io::Error> {
-    let table = parser_table();
-
-    // First write each unique fast table.
+/// Write the parser tables.
+///
+/// If `compact` is true, the tables are written as [u16] values (see
+/// `PackedEntry` in src/tables.rs), and the full tables are only written
+/// for testing.
+fn write_parser_tables(w: &mut impl Write, compact: bool) -> Result<(), std::io::Error> {
+    let tables = [
+        ("PARSER_TABLE", parser_table(KatcpVersion::V5)),
+        ("PARSER_TABLE_V4", parser_table(KatcpVersion::V4)),
+    ];
+
+    // First write each unique fast table (shared between the versions).
     let mut fast_table_names: HashMap<Rc<EnumMap<u8, bool>>, String> = HashMap::new();
     let mut counter = 0;
-    for row in table.values() {
+    for row in tables.iter().flat_map(|(_, table)| table.values()) {
         for entry in row.values() {
             if let Some(fast) = &entry.fast_table {
                 let old_counter = counter;
@@ -263,20 +329,123 @@ fn write_parser_tables(w: &mut impl Write) -> Result<(), std::io::Error> {
                 });
                 if counter != old_counter {
                     // This is a new entry
-                    writeln!(w, "const {name}: EnumMap<u8, bool> = EnumMap::from_array([")?;
+                    writeln!(w, "const {name}: FastTable = FastTable {{")?;
+                    writeln!(w, "    accept: EnumMap::from_array([")?;
                     for i in 0..=255u8 {
-                        writeln!(w, "    {},", fast[i])?;
+                        writeln!(w, "        {},", fast[i])?;
+                    }
+                    writeln!(w, "    ]),")?;
+                    let ranges = reject_ranges(fast);
+                    if ranges.len() <= MAX_REJECT_RANGES {
+                        writeln!(w, "    reject_ranges: Some(&{ranges:?}),")?;
+                    } else {
+                        writeln!(w, "    reject_ranges: None,")?;
                     }
-                    writeln!(w, "]);")?;
+                    writeln!(w, "}};")?;
                 }
             }
         }
     }
 
     // Now write the entries.
+    for (table_name, table) in &tables {
+        if compact {
+            writeln!(w, "#[cfg(test)]")?;
+            let full_name = format!("{table_name}_FULL");
+            write_parser_table(w, &full_name, table, &fast_table_names)?;
+        } else {
+            write_parser_table(w, table_name, table, &fast_table_names)?;
+        }
+    }
+    if compact {
+        write_packed_tables(w, &tables, &fast_table_names)?;
+    }
+    Ok(())
+}
+
+/// Transition table for the whole state machine
+type Table = EnumMap<State, EnumMap<u8, Entry>>;
+
+// The state is packed into 4 bits
+const _: () = assert!(State::LENGTH <= 16, "too many states to pack");
+
+fn write_packed_tables(
+    w: &mut impl Write,
+    tables: &[(&str, Table)],
+    fast_table_names: &HashMap<Rc<EnumMap<u8, bool>>, String>,
+) -> Result<(), std::io::Error> {
+    // Number the unique actions and fast tables
+    let mut actions: Vec<Action> = vec![];
+    for row in tables.iter().flat_map(|(_, table)| table.values()) {
+        for entry in row.values() {
+            if !actions.contains(&entry.action) {
+                actions.push(entry.action.clone());
+            }
+        }
+    }
+    let mut fast_tables: Vec<&String> = fast_table_names.values().collect();
+    // Sort in numeric order, so that the output is deterministic
+    fast_tables.sort_by_key(|name| (name.len(), name.as_str()));
+    assert!(actions.len() <= 32, "too many actions to pack");
+    assert!(fast_tables.len() < 64, "too many fast tables to pack");
+
+    writeln!(
+        w,
+        "pub(crate) const ACTIONS: [Action; {}] = [",
+        actions.len()
+    )?;
+    for action in &actions {
+        writeln!(w, "    Action::{action:?},")?;
+    }
+    writeln!(w, "];")?;
+    writeln!(
+        w,
+        "pub(crate) const FAST_TABLES: [&FastTable; {}] = [",
+        fast_tables.len()
+    )?;
+    for name in &fast_tables {
+        writeln!(w, "    &{name},")?;
+    }
+    writeln!(w, "];")?;
+
+    for (table_name, table) in tables {
+        writeln!(
+            w,
+            "pub(crate) const {table_name}: ParserTable = EnumMap::from_array(["
+        )?;
+        for row in table.values() {
+            writeln!(w, "    EnumMap::from_array([")?;
+            for entry in row.values() {
+                let action = actions.iter().position(|a| *a == entry.action).unwrap();
+                let fast_table = match &entry.fast_table {
+                    Some(fast_table) => {
+                        let name = &fast_table_names[fast_table];
+                        fast_tables.iter().position(|n| *n == name).unwrap() + 1
+                    }
+                    None => 0,
+                };
+                let packed = entry.state.into_usize()
+                    | (action << 4)
+                    | ((entry.create_argument as usize) << 9)
+                    | (fast_table << 10);
+                writeln!(w, "        PackedEntry({packed:#06x}),")?;
+            }
+            writeln!(w, "    ]),")?;
+        }
+        writeln!(w, "]);")?;
+    }
+    Ok(())
+}
+
+fn write_parser_table(
+    w: &mut impl Write,
+    table_name: &str,
+    table: &EnumMap<State, EnumMap<u8, Entry>>,
+    fast_table_names: &HashMap<Rc<EnumMap<u8, bool>>, String>,
+) -> Result<(), std::io::Error> {
     writeln!(
         w,
-        "pub(crate) const PARSER_TABLE: EnumMap<State, EnumMap<u8, Entry>> = EnumMap::from_array(["
+        "pub(crate) const {table_name}: EnumMap<State, EnumMap<u8, Entry>> = EnumMap::from_array(["
     )?;
     for row in table.values() {
         writeln!(w, "    EnumMap::from_array([")?;
@@ -346,7 +515,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     let tables_path = out_path.join("tables.rs");
     let mut tables_writer = fs::File::create(tables_path)?;
 
-    write_parser_tables(&mut tables_writer)?;
+    let compact = std::env::var_os("CARGO_FEATURE_COMPACT_TABLES").is_some();
+    write_parser_tables(&mut tables_writer, compact)?;
     write_format_tables(&mut tables_writer)?;
     drop(tables_writer);
 
@@ -13,6 +13,15 @@
  * limitations under the License.
  */
 
+//! State machine definitions shared by katcp-codec and its build script.
+//!
+//! This crate is `no_std` unless the `std` feature (implied by `pyo3`) is
+//! enabled.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+use core::fmt;
+use core::str::FromStr;
 use enum_map::Enum;
 
 /// Type of katcp message
@@ -20,6 +29,11 @@ use enum_map::Enum;
     feature = "pyo3",
     pyo3::pyclass(module = "katcp_codec._lib", rename_all = "SCREAMING_SNAKE_CASE")
 )]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "SCREAMING_SNAKE_CASE")
+)]
 #[derive(Copy, Clone, Eq, PartialEq, Debug, Hash)]
 pub enum MessageType {
     Request = 1,
@@ -27,6 +41,112 @@ pub enum MessageType {
     Inform = 3,
 }
 
+#[cfg(feature = "pyo3")]
+#[pyo3::pymethods]
+impl MessageType {
+    /// Support pickling, by looking up the member by name on unpickling.
+    fn __reduce__(
+        slf: &pyo3::Bound<'_, Self>,
+    ) -> pyo3::PyResult<(pyo3::PyObject, (pyo3::PyObject, &'static str))> {
+        use pyo3::prelude::*;
+
+        let py = slf.py();
+        let getattr = py.import_bound("builtins")?.getattr("getattr")?;
+        let name = slf.borrow().py_name();
+        Ok((
+            getattr.unbind(),
+            (py.get_type_bound::<Self>().into_any().unbind(), name),
+        ))
+    }
+}
+
+impl MessageType {
+    /// The character that introduces a message of this type on the wire.
+    pub const fn symbol(self) -> u8 {
+        match self {
+            MessageType::Request => b'?',
+            MessageType::Reply => b'!',
+            MessageType::Inform => b'#',
+        }
+    }
+
+    /// Look up the message type from the character that introduces it on
+    /// the wire.
+    pub const fn from_symbol(symbol: u8) -> Option<Self> {
+        match symbol {
+            b'?' => Some(MessageType::Request),
+            b'!' => Some(MessageType::Reply),
+            b'#' => Some(MessageType::Inform),
+            _ => None,
+        }
+    }
+
+    /// Name of the member of the Python enum, as produced by the
+    /// `SCREAMING_SNAKE_CASE` renaming of the variant.
+    pub const fn py_name(self) -> &'static str {
+        match self {
+            MessageType::Request => "REQUEST",
+            MessageType::Reply => "REPLY",
+            MessageType::Inform => "INFORM",
+        }
+    }
+
+    /// Lower-case name of the message type, as used by [Display](fmt::Display)
+    /// and [FromStr].
+    const fn name(self) -> &'static str {
+        match self {
+            MessageType::Request => "request",
+            MessageType::Reply => "reply",
+            MessageType::Inform => "inform",
+        }
+    }
+}
+
+impl fmt::Display for MessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Error returned when parsing an unknown [MessageType] name.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub struct UnknownMessageType;
+
+impl fmt::Display for UnknownMessageType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("unknown message type (expected request, reply or inform)")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for UnknownMessageType {}
+
+impl FromStr for MessageType {
+    type Err = UnknownMessageType;
+
+    /// Parse the lower-case name of a message type.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        [
+            MessageType::Request,
+            MessageType::Reply,
+            MessageType::Inform,
+        ]
+        .into_iter()
+        .find(|mtype| mtype.name() == s)
+        .ok_or(UnknownMessageType)
+    }
+}
+
+/// Version of the katcp protocol
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum KatcpVersion {
+    /// Version 4, which has no message IDs
+    V4,
+    /// Version 5
+    #[default]
+    V5,
+}
+
 /// State in the state machine
 #[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash, Enum)]
 pub enum State {
@@ -44,12 +164,18 @@ pub enum State {
     Id,
     /// After the ] terminating the message ID
     AfterId,
+    /// After : in a message ID using the colon syntax
+    BeforeColonId,
+    /// Middle of a message ID using the colon syntax
+    ColonId,
     /// Seen some whitespace, haven't started the next argument yet
     BeforeArgument,
     /// Middle of an argument, not following a backslash
     Argument,
     /// Seen a backslash in an argument
     ArgumentEscape,
+    /// Message type is being filtered out, waiting for the end-of-line
+    Skip,
     /// Invalid character seen, waiting for the end-of-line
     #[default]
     Error,
@@ -84,6 +210,18 @@ pub enum Action {
     SetType(MessageType),
     /// Set line_length back to 0 (after empty message)
     ResetLineLength,
+    /// Start a message ID (subject to the parser options)
+    StartId,
+    /// Start a message ID using the colon syntax (an error unless enabled)
+    ColonId,
+    /// Closing bracket of a message ID
+    EndId,
+    /// Whitespace immediately following the name, message ID or an argument
+    Separator,
+    /// Whitespace following other whitespace
+    ExtraSeparator,
+    /// Reset the parser at the end of a line that was skipped
+    EndSkip,
     /// Set an error message
     Error,
 }
@@ -92,7 +230,12 @@ impl Action {
     pub fn is_mergeable(&self) -> bool {
         matches!(
             self,
-            Action::Nothing | Action::Name | Action::Id | Action::Argument | Action::Error
+            Action::Nothing
+                | Action::Name
+                | Action::Id
+                | Action::Argument
+                | Action::ExtraSeparator
+                | Action::Error
         )
     }
 }
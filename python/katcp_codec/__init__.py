@@ -16,13 +16,21 @@
 
 import enum
 import re
-from dataclasses import InitVar, dataclass, field
-from typing import List, Optional, Union
+from dataclasses import dataclass
+from typing import Iterator, List, Optional, Sequence, Tuple, Union, cast
 
 from . import _lib
 
 _NAME_RE = re.compile(b"[A-Za-z][-A-Za-z0-9]*")
 
+#: Error returned by :meth:`Parser.append` for an invalid message. It is a
+#: subclass of :exc:`ValueError`, with attributes ``position`` (the 1-based
+#: position of the offending character within the line), ``stream_position``
+#: (the 0-based offset of that character from the start of all the data
+#: given to the :class:`Parser`) and ``message`` (a description of the
+#: problem, without the position).
+ParseError = _lib.ParseError
+
 
 # Note: the values must correspond to those in message.rs
 class MessageType(enum.Enum):
@@ -40,16 +48,49 @@ _MESSAGE_TYPE_MAP = {
 }
 
 
-@dataclass
+def _to_bytes(value: Union[str, bytes], what: str) -> bytes:
+    if isinstance(value, bytes):
+        return value
+    elif isinstance(value, str):
+        return value.encode("utf-8")
+    else:
+        raise TypeError(f"{what} must be str or bytes")
+
+
+def _encode_argument(value: Union[str, bytes, bool, int, float]) -> bytes:
+    if isinstance(value, bytes):
+        return value
+    elif isinstance(value, str):
+        return value.encode("utf-8")
+    elif isinstance(value, bool):  # Must be before int, as bool is a subclass
+        return b"1" if value else b"0"
+    elif isinstance(value, (int, float)):
+        return repr(value).encode("ascii")
+    else:
+        raise TypeError(f"cannot encode {type(value).__name__} as an argument")
+
+
+@dataclass(init=False)
 class Message:
     """A katcp message.
 
+    The constructor accepts the name and arguments as :class:`str`, which it
+    encodes as UTF-8, and stores them as :class:`bytes` (with the arguments
+    in a :class:`list`). Assigning to the attributes afterwards does no such
+    conversion, so only :class:`bytes` should be assigned.
+
+    If `validate` is false, the name and message ID are not checked. This
+    should only be done if they've already been checked, as the behaviour is
+    undefined if they're invalid.
+
     Raises
     ------
     OverflowError
         if the message ID is out of range
     ValueError
         if the name does not conform to the specification
+    TypeError
+        if the name or an argument is neither :class:`str` nor :class:`bytes`
     """
 
     # Python 3.8 doesn't support the `slots=True` parameter to dataclass, so
@@ -64,12 +105,23 @@ class Message:
     mid: Optional[int]
     #: Message arguments
     arguments: List[bytes]
-    #: If false, skip validating the name and message ID. This should only be
-    #: done if they've already been checked, as the behaviour is undefined if
-    #: they're invalid.
-    validate: InitVar[bool] = field(default=True)
 
-    def __post_init__(self, validate: bool) -> None:
+    def __init__(
+        self,
+        mtype: MessageType,
+        name: Union[str, bytes],
+        mid: Optional[int],
+        arguments: Sequence[Union[str, bytes]],
+        validate: bool = True,
+    ) -> None:
+        self.mtype = mtype
+        self.name = _to_bytes(name, "name")
+        self.mid = mid
+        if type(arguments) is list and all(isinstance(arg, bytes) for arg in arguments):
+            # A list of bytes is kept as is
+            self.arguments = cast(List[bytes], arguments)
+        else:
+            self.arguments = [_to_bytes(arg, "argument") for arg in arguments]
         if not validate:
             return
         if self.mid is not None and not 1 <= self.mid <= 2**31 - 1:
@@ -77,10 +129,105 @@ class Message:
         if not _NAME_RE.fullmatch(self.name):
             raise ValueError("Name is invalid")
 
+    @classmethod
+    def request(
+        cls,
+        name: Union[str, bytes],
+        *args: Union[str, bytes, bool, int, float],
+        mid: Optional[int] = None,
+    ) -> "Message":
+        """Construct a request.
+
+        Arguments may be :class:`bytes`, :class:`str` (encoded as UTF-8),
+        :class:`bool` (encoded as 0 or 1), :class:`int` or :class:`float`.
+
+        Raises
+        ------
+        TypeError
+            if an argument has any other type
+        """
+        return cls._with_type(MessageType.REQUEST, name, args, mid)
+
+    @classmethod
+    def reply(
+        cls,
+        name: Union[str, bytes],
+        *args: Union[str, bytes, bool, int, float],
+        mid: Optional[int] = None,
+    ) -> "Message":
+        """Construct a reply. See :meth:`request` for the argument types."""
+        return cls._with_type(MessageType.REPLY, name, args, mid)
+
+    @classmethod
+    def inform(
+        cls,
+        name: Union[str, bytes],
+        *args: Union[str, bytes, bool, int, float],
+        mid: Optional[int] = None,
+    ) -> "Message":
+        """Construct an inform. See :meth:`request` for the argument types."""
+        return cls._with_type(MessageType.INFORM, name, args, mid)
+
+    @classmethod
+    def _with_type(
+        cls,
+        mtype: MessageType,
+        name: Union[str, bytes],
+        args: Tuple[Union[str, bytes, bool, int, float], ...],
+        mid: Optional[int],
+    ) -> "Message":
+        arguments = [_encode_argument(arg) for arg in args]
+        return cls(mtype, name, mid, arguments)
+
     def __bytes__(self) -> bytes:
         """Convert the message to its wire representation."""
         return bytes(_message_to_rust(self))
 
+    def write_into(self, buffer: bytearray) -> int:
+        """Write the wire representation to the start of `buffer`.
+
+        This avoids allocating a new :class:`bytes` for each message.
+
+        Returns
+        -------
+        size
+            The number of bytes written
+
+        Raises
+        ------
+        ValueError
+            if `buffer` is too small
+        """
+        return _message_to_rust(self).write_into(buffer)
+
+    @property
+    def encoded_size(self) -> int:
+        """Number of bytes in the wire representation.
+
+        This is computed without encoding the message, and is always equal
+        to ``len(bytes(self))``.
+        """
+        return _message_to_rust(self).encoded_size
+
+    @property
+    def argument_count(self) -> int:
+        """Number of arguments."""
+        return len(self.arguments)
+
+    def argument_at(self, index: int) -> bytes:
+        """Get a single argument.
+
+        The arguments are stored as :class:`bytes` objects, so this returns
+        the stored object without copying it. A :class:`memoryview` would
+        not avoid any further work, so none is provided.
+
+        Raises
+        ------
+        IndexError
+            if `index` is out of range
+        """
+        return self.arguments[index]
+
 
 def _message_from_rust(
     message: Union[_lib.Message, ValueError]
@@ -88,12 +235,26 @@ def _message_from_rust(
     if isinstance(message, ValueError):
         return message
     else:
-        return Message(
-            MessageType(int(message.mtype)),
-            message.name,
-            message.mid,
-            message.arguments,
-        )
+        return _valid_message_from_rust(message)
+
+
+def _valid_message_from_rust(message: _lib.Message) -> Message:
+    return Message(
+        MessageType(int(message.mtype)),
+        message.name,
+        message.mid,
+        message.arguments,
+    )
+
+
+class _MessageIterator(Iterator[Message]):
+    """Iterator returned by :meth:`Parser.iter_append`."""
+
+    def __init__(self, it: _lib.ParserIterator) -> None:
+        self._it = it
+
+    def __next__(self) -> Message:
+        return _valid_message_from_rust(next(self._it))
 
 
 def _message_to_rust(message: Message) -> _lib.Message:
@@ -111,6 +272,11 @@ class Parser:
     The parser accepts chunks of data from the wire (which need not be aligned
     to message boundaries) and returns whole messages as they are parsed.
 
+    :meth:`append` releases the GIL while parsing, so separate parsers can
+    be used concurrently from different threads. A single parser must not be
+    shared between threads: a call made while another thread is appending to
+    the same parser raises :exc:`RuntimeError`.
+
     Parameters
     ----------
     max_line_length
@@ -129,10 +295,42 @@ class Parser:
         messages
             Messages whose end was in the input data. Each message is either
             an instance of :class:`Message` if it was valid or
-            :exc:`ValueError` if not.
+            :exc:`ParseError` (a subclass of :exc:`ValueError`) if not.
         """
         return [_message_from_rust(message) for message in self._parser.append(data)]
 
+    def iter_append(self, data: bytes) -> Iterator[Message]:
+        """Append new data to the parser, parsing it lazily.
+
+        This is equivalent to :meth:`append`, but messages are parsed one
+        at a time as the returned iterator is advanced, instead of all being
+        held in a list. An invalid message causes :exc:`ParseError` to be
+        raised, after which iteration may continue with the next message.
+        Until the iterator is exhausted or garbage-collected, the other
+        methods of the parser raise :exc:`RuntimeError`.
+        """
+        return _MessageIterator(self._parser.iter_append(data))
+
+    def finish(self) -> Optional[Union[Message, ValueError]]:
+        """Signal the end of the input.
+
+        If there is a partial line, it is completed as if a newline had been
+        received. If this is not valid (for example, if the line ends
+        partway through an escape sequence or a message ID), a
+        :exc:`ParseError` is returned instead. The parser is left in its
+        initial state.
+
+        Returns
+        -------
+        message
+            The final message or error, or ``None`` if there was no partial
+            line.
+        """
+        message = self._parser.finish()
+        if message is None:
+            return None
+        return _message_from_rust(message)
+
     def reset(self) -> None:
         """Reset the parser to its initial state.
 
@@ -149,3 +347,11 @@ class Parser:
         those bytes may have already been parsed into internal structures.
         """
         return self._parser.buffer_size
+
+    @property
+    def stream_offset(self) -> int:
+        """Get the total number of bytes passed to :meth:`append`.
+
+        This is not cleared by :meth:`reset`.
+        """
+        return self._parser.stream_offset
@@ -14,7 +14,7 @@
 # limitations under the License.
 ################################################################################
 
-from typing import Final, List, Optional, Union
+from typing import Final, Iterator, List, Optional, Union
 
 # Not inherited from enum.Enum, because Pyo3 doesn't provide full compatibility.
 class MessageType:
@@ -24,6 +24,11 @@ class MessageType:
 
     def __int__(self) -> int: ...
 
+class ParseError(ValueError):
+    position: int
+    stream_position: int
+    message: str
+
 class Message:
     mtype: MessageType
     name: bytes
@@ -34,15 +39,31 @@ class Message:
     def __init__(
         self,
         mtype: MessageType,
-        name: bytes,
+        name: Union[str, bytes],
         mid: Optional[int],
-        arguments: List[bytes],
+        arguments: List[Union[str, bytes]],
     ) -> None: ...
     def __bytes__(self) -> bytes: ...
+    def __eq__(self, other: object) -> bool: ...
+    __hash__: None  # type: ignore[assignment]
+    def write_into(self, buffer: bytearray) -> int: ...
+    @property
+    def argument_count(self) -> int: ...
+    @property
+    def encoded_size(self) -> int: ...
+    def argument_at(self, index: int) -> bytes: ...
+
+class ParserIterator(Iterator[Message]):
+    def __iter__(self) -> ParserIterator: ...
+    def __next__(self) -> Message: ...
 
 class Parser:
     def __init__(self, max_line_length: int) -> None: ...
     def append(self, data: bytes) -> List[Union[Message, ValueError]]: ...
+    def iter_append(self, data: bytes) -> ParserIterator: ...
+    def finish(self) -> Optional[Union[Message, ValueError]]: ...
     def reset(self) -> None: ...
     @property
     def buffer_size(self) -> int: ...
+    @property
+    def stream_offset(self) -> int: ...
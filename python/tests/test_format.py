@@ -14,9 +14,11 @@
 # limitations under the License.
 ################################################################################
 
+import pickle
+
 import pytest
 
-from katcp_codec import Message, MessageType
+from katcp_codec import Message, MessageType, _lib
 
 
 @pytest.mark.parametrize("mid", [-1, 0, -(2**31) - 1, 2**31])
@@ -48,3 +50,123 @@ def test_bad_name(name: bytes) -> None:
 )
 def test_success(message: Message, encoding: bytes) -> None:
     assert bytes(message) == encoding
+    assert message.encoded_size == len(encoding)
+
+
+def test_str_fields() -> None:
+    arguments = ["caf\u00e9", b"\xff"]
+    message = Message(MessageType.REQUEST, "hello", None, arguments)
+    assert message.name == b"hello"
+    assert message.arguments == [b"caf\xc3\xa9", b"\xff"]
+    assert bytes(message) == b"?hello caf\xc3\xa9 \xff\n"
+    # Other sequences are stored as a list
+    message = Message(MessageType.REQUEST, b"hello", None, (b"a", b"b"))
+    assert message.arguments == [b"a", b"b"]
+    assert bytes(message) == b"?hello a b\n"
+    assert message.encoded_size == len(b"?hello a b\n")
+    # Strings are converted even if validation is skipped
+    message = Message(MessageType.REQUEST, "hello", None, ["a"], validate=False)
+    assert message.name == b"hello"
+    assert message.arguments == [b"a"]
+    with pytest.raises(TypeError):
+        Message(MessageType.REQUEST, b"hello", None, [1])  # type: ignore[list-item]
+
+
+def test_rust_str_fields() -> None:
+    message = _lib.Message(_lib.MessageType.REQUEST, "hello", None, ["a", b"b"])
+    assert message.name == b"hello"
+    assert message.arguments == [b"a", b"b"]
+    message.name = "bye"  # type: ignore[assignment]
+    message.arguments = ["c"]  # type: ignore[list-item]
+    assert bytes(message) == b"?bye c\n"
+    assert message.encoded_size == len(b"?bye c\n")
+    # A list of bytes is kept as is
+    arguments = [b"d"]
+    message.arguments = arguments
+    assert message.arguments is arguments
+    with pytest.raises(TypeError):
+        message.name = 3  # type: ignore[assignment]
+    with pytest.raises(TypeError):
+        _lib.Message(
+            _lib.MessageType.REQUEST, "hello", None, [None]  # type: ignore[list-item]
+        )
+
+
+@pytest.mark.parametrize(
+    "message, encoding",
+    [
+        (Message.request("hello"), b"?hello\n"),
+        (Message.reply(b"set", "ok", True, False, mid=3), b"!set[3] ok 1 0\n"),
+        (Message.inform("log", -42, 0.25, b"\xff"), b"#log -42 0.25 \xff\n"),
+        (Message.inform("nan", float("nan"), float("-inf")), b"#nan nan -inf\n"),
+    ],
+)
+def test_constructors(message: Message, encoding: bytes) -> None:
+    assert bytes(message) == encoding
+
+
+def test_constructors_bad() -> None:
+    with pytest.raises(TypeError):
+        Message.request("hello", None)  # type: ignore[arg-type]
+    with pytest.raises(OverflowError):
+        Message.reply("hello", mid=0)
+    with pytest.raises(ValueError):
+        Message.inform("bad_name")
+
+
+def test_argument_access() -> None:
+    message = Message(MessageType.REQUEST, b"hello", None, [b"foo", b""])
+    assert message.argument_count == 2
+    assert message.argument_at(0) is message.arguments[0]
+    assert message.argument_at(1) == b""
+    assert message.argument_at(-2) is message.arguments[0]
+    with pytest.raises(IndexError):
+        message.argument_at(2)
+    with pytest.raises(IndexError):
+        message.argument_at(-3)
+
+
+def test_rust_argument_access() -> None:
+    message = _lib.Message(_lib.MessageType.REQUEST, b"hello", None, [b"foo", b""])
+    assert message.argument_at(0) == b"foo"
+    assert message.argument_at(-1) == b""
+    assert message.argument_at(-2) == b"foo"
+    for index in [2, -3]:
+        with pytest.raises(IndexError):
+            message.argument_at(index)
+
+
+def test_write_into() -> None:
+    message = Message(MessageType.REQUEST, b"hello", None, [b"foo bar"])
+    buffer = bytearray(b"x" * 20)
+    assert message.write_into(buffer) == 16
+    assert buffer == b"?hello foo\\_bar\nxxxx"
+    with pytest.raises(ValueError):
+        message.write_into(bytearray(15))
+
+
+def test_rust_repr() -> None:
+    message = _lib.Message(_lib.MessageType.REPLY, b"help", 3, [b"\xff\n"])
+    assert repr(message) == r"Message(REPLY, b'help', mid=3, arguments=[b'\xff\n'])"
+
+
+def test_rust_eq() -> None:
+    message = _lib.Message(_lib.MessageType.REPLY, b"help", 3, [b"ok"])
+    assert message == _lib.Message(_lib.MessageType.REPLY, b"help", 3, [b"ok"])
+    assert message != _lib.Message(_lib.MessageType.REPLY, b"help", 3, [b"fail"])
+    assert message != _lib.Message(_lib.MessageType.INFORM, b"help", 3, [b"ok"])
+    assert message != _lib.Message(_lib.MessageType.REPLY, b"help", None, [b"ok"])
+    assert message != "help"
+    with pytest.raises(TypeError):
+        hash(message)
+
+
+@pytest.mark.parametrize(
+    "message",
+    [
+        _lib.Message(_lib.MessageType.INFORM, b"binary", 7, [b"\xff\0\n", b""]),
+        Message(MessageType.REQUEST, b"binary", None, [b"\xff\0\n", b""]),
+    ],
+)
+def test_pickle(message: object) -> None:
+    assert pickle.loads(pickle.dumps(message)) == message
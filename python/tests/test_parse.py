@@ -14,11 +14,15 @@
 # limitations under the License.
 ################################################################################
 
-from typing import List
+import sys
+import threading
+import time
+from concurrent.futures import ThreadPoolExecutor
+from typing import List, Tuple, Union
 
 import pytest
 
-from katcp_codec import Message, MessageType, Parser
+from katcp_codec import Message, MessageType, ParseError, Parser
 
 
 @pytest.fixture
@@ -75,3 +79,105 @@ def test_reset(parser: Parser) -> None:
     assert parser.append(b"!reply\n") == [
         Message(MessageType.REPLY, b"reply", None, [])
     ]
+
+
+def test_error(parser: Parser) -> None:
+    (error,) = parser.append(b"?hello[x]\n")
+    assert isinstance(error, ParseError)
+    assert isinstance(error, ValueError)
+    assert error.position == 8
+    assert error.stream_position == 7
+    assert error.message == "Invalid character"
+    assert str(error) == '"Invalid character" at character 8'
+
+
+def test_stream_position(parser: Parser) -> None:
+    parser.append(b"?first\n?sec")
+    (error,) = parser.append(b"ond[x]\n")
+    assert error.position == 9
+    assert error.stream_position == 15
+    assert parser.stream_offset == 18
+
+
+def test_finish(parser: Parser) -> None:
+    assert parser.finish() is None
+    parser.append(b"?hello world")
+    assert parser.finish() == Message(MessageType.REQUEST, b"hello", None, [b"world"])
+    parser.append(b"?hello[1")
+    error = parser.finish()
+    assert isinstance(error, ParseError)
+    assert error.message == "Unexpected end of input"
+    assert parser.buffer_size == 0
+
+
+def test_iter_append(parser: Parser) -> None:
+    it = parser.iter_append(b"?first\n?bad\0\n#second x\n?par")
+    assert next(it) == Message(MessageType.REQUEST, b"first", None, [])
+    with pytest.raises(ParseError):
+        next(it)
+    assert list(it) == [Message(MessageType.INFORM, b"second", None, [b"x"])]
+    assert parser.append(b"tial\n") == [
+        Message(MessageType.REQUEST, b"partial", None, [])
+    ]
+
+
+def test_iter_append_active(parser: Parser) -> None:
+    it = parser.iter_append(b"?a\n?b\n?par")
+    assert next(it) == Message(MessageType.REQUEST, b"a", None, [])
+    with pytest.raises(RuntimeError):
+        parser.append(b"?c\n")
+    with pytest.raises(RuntimeError):
+        parser.iter_append(b"?c\n")
+    with pytest.raises(RuntimeError):
+        parser.finish()
+    with pytest.raises(RuntimeError):
+        parser.reset()
+    assert list(it) == [Message(MessageType.REQUEST, b"b", None, [])]
+    assert parser.append(b"tial\n") == [
+        Message(MessageType.REQUEST, b"partial", None, [])
+    ]
+
+
+def test_iter_append_dropped(parser: Parser) -> None:
+    it = parser.iter_append(b"?a\n?b\n")
+    assert next(it) == Message(MessageType.REQUEST, b"a", None, [])
+    del it
+    assert parser.append(b"?c\n") == [Message(MessageType.REQUEST, b"c", None, [])]
+
+
+def test_append_threads() -> None:
+    """Test that parsers in different threads don't block other threads."""
+    n_threads = 4
+    # Escape sequences make the parse slow enough for the ticker to run
+    argument = b"x " * (1024 * 1024)
+    data = b"?big " + b"x\\_" * (1024 * 1024) + b"\n"
+    ticks = [0]
+    stop = threading.Event()
+
+    def tick() -> None:
+        while not stop.is_set():
+            ticks[0] += 1
+            time.sleep(0.0001)
+
+    def parse(index: int) -> Tuple[List[Union[Message, ValueError]], int]:
+        parser = Parser(len(data))
+        before = ticks[0]
+        messages = parser.append(data)
+        return messages, ticks[0] - before
+
+    # Prevent the interpreter from forcing a thread switch, so that the
+    # ticker can only run while parsing if append releases the GIL.
+    old_interval = sys.getswitchinterval()
+    sys.setswitchinterval(1000)
+    ticker = threading.Thread(target=tick)
+    ticker.start()
+    try:
+        with ThreadPoolExecutor(n_threads) as pool:
+            results = list(pool.map(parse, range(n_threads)))
+    finally:
+        stop.set()
+        ticker.join()
+        sys.setswitchinterval(old_interval)
+    for messages, progress in results:
+        assert messages == [Message(MessageType.REQUEST, b"big", None, [argument])]
+        assert progress > 0
@@ -13,15 +13,26 @@
  * limitations under the License.
  */
 
+use pyo3::create_exception;
+use pyo3::exceptions::PyValueError;
 use pyo3::prelude::*;
 
 use crate::message::{MessageType, PyMessage};
-use crate::parse::Parser;
+use crate::parse::{Parser, ParserIterator};
+
+create_exception!(
+    katcp_codec._lib,
+    ParseError,
+    PyValueError,
+    "Error from parsing a message."
+);
 
 #[pymodule]
 fn _lib(m: Bound<'_, PyModule>) -> PyResult<()> {
     m.add_class::<MessageType>()?;
     m.add_class::<PyMessage>()?;
     m.add_class::<Parser>()?;
+    m.add_class::<ParserIterator>()?;
+    m.add("ParseError", m.py().get_type_bound::<ParseError>())?;
     Ok(())
 }
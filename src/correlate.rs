@@ -0,0 +1,315 @@
+/* Copyright (c) 2024, National Research Foundation (SARAO)
+ *
+ * Licensed under the BSD 3-Clause License (the "License"); you may not use
+ * this file except in compliance with the License. You may obtain a copy
+ * of the License at
+ *
+ *   https://opensource.org/licenses/BSD-3-Clause
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Matching of replies (and informs) to the requests that caused them
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+use crate::message::{Message, MessageType, OwnedMessage};
+
+/// Error returned by [Correlator::register].
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum RegisterError {
+    #[error("message is not a request")]
+    NotRequest,
+    #[error("request has no message ID")]
+    MissingId,
+    #[error("message ID {0} is already in use")]
+    DuplicateId(u32),
+}
+
+/// Result of passing a reply to [Correlator::handle].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Correlation<Q, R> {
+    /// The reply matches an outstanding request, which is no longer tracked.
+    /// The informs that matched the request are returned in the order they
+    /// arrived.
+    Matched {
+        request: Q,
+        informs: Vec<OwnedMessage>,
+        reply: R,
+    },
+    /// The reply has no message ID, or does not match the name and message
+    /// ID of any outstanding request.
+    Unmatched(R),
+}
+
+/// A request awaiting its reply
+#[derive(Debug)]
+struct Pending<Q> {
+    request: Q,
+    sent: Instant,
+    informs: Vec<OwnedMessage>,
+}
+
+/// Tracks outgoing requests and matches them with incoming replies.
+///
+/// A reply or inform matches a request if it has the same name and message
+/// ID. Informs are held until the reply arrives, so that each request is
+/// completed together with its informs. Requests without a reply can be
+/// discarded with [Correlator::expire].
+#[derive(Debug)]
+pub struct Correlator<N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    pending: HashMap<u32, Pending<Message<N, A>>>,
+}
+
+impl<N, A> Default for Correlator<N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    fn default() -> Self {
+        Self {
+            pending: HashMap::new(),
+        }
+    }
+}
+
+impl<N, A> Correlator<N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    /// Create a correlator with no outstanding requests.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of outstanding requests.
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Whether there are no outstanding requests.
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    /// Record a request that was sent at time `sent`.
+    ///
+    /// The request must have a message ID which is not used by another
+    /// outstanding request.
+    pub fn register(&mut self, request: Message<N, A>, sent: Instant) -> Result<(), RegisterError> {
+        if request.mtype != MessageType::Request {
+            return Err(RegisterError::NotRequest);
+        }
+        let mid = request.mid.ok_or(RegisterError::MissingId)?;
+        if self.pending.contains_key(&mid) {
+            return Err(RegisterError::DuplicateId(mid));
+        }
+        self.pending.insert(
+            mid,
+            Pending {
+                request,
+                sent,
+                informs: vec![],
+            },
+        );
+        Ok(())
+    }
+
+    /// Process an incoming message.
+    ///
+    /// Informs that match an outstanding request are held until its reply
+    /// arrives. Other informs and requests are ignored. In both cases [None]
+    /// is returned.
+    pub fn handle<N2, A2>(
+        &mut self,
+        message: Message<N2, A2>,
+    ) -> Option<Correlation<Message<N, A>, Message<N2, A2>>>
+    where
+        N2: AsRef<[u8]> + Into<Vec<u8>>,
+        A2: AsRef<[u8]> + Into<Vec<u8>>,
+    {
+        if message.mtype == MessageType::Request {
+            return None;
+        }
+        let matched = message.mid.filter(|mid| {
+            self.pending
+                .get(mid)
+                .is_some_and(|pending| pending.request.name.as_ref() == message.name.as_ref())
+        });
+        if message.mtype == MessageType::Inform {
+            if let Some(mid) = matched {
+                let pending = self.pending.get_mut(&mid).unwrap();
+                pending.informs.push(message.into_owned());
+            }
+            return None;
+        }
+        Some(match matched {
+            Some(mid) => {
+                let pending = self.pending.remove(&mid).unwrap();
+                Correlation::Matched {
+                    request: pending.request,
+                    informs: pending.informs,
+                    reply: message,
+                }
+            }
+            None => Correlation::Unmatched(message),
+        })
+    }
+
+    /// Remove and return requests that were sent at least `timeout` before
+    /// `now`. They are returned in the order they were sent. Any informs
+    /// held for them are discarded.
+    pub fn expire(&mut self, now: Instant, timeout: Duration) -> Vec<Message<N, A>> {
+        let mut expired = vec![];
+        for (mid, pending) in std::mem::take(&mut self.pending) {
+            if now.saturating_duration_since(pending.sent) >= timeout {
+                expired.push(pending);
+            } else {
+                self.pending.insert(mid, pending);
+            }
+        }
+        expired.sort_by_key(|pending| pending.sent);
+        expired.into_iter().map(|pending| pending.request).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rstest::*;
+
+    use crate::message::OwnedMessage;
+
+    fn message(mtype: MessageType, name: &[u8], mid: Option<u32>) -> OwnedMessage {
+        Message::new(mtype, name.to_vec(), mid, vec![])
+    }
+
+    #[fixture]
+    fn correlator() -> Correlator<Vec<u8>, Vec<u8>> {
+        Correlator::new()
+    }
+
+    #[rstest]
+    fn test_matched(mut correlator: Correlator<Vec<u8>, Vec<u8>>) {
+        let now = Instant::now();
+        let request = message(MessageType::Request, b"foo", Some(7));
+        correlator.register(request.clone(), now).unwrap();
+        // Informs and mismatched replies do not complete the request
+        let inform = message(MessageType::Inform, b"foo", Some(7));
+        assert_eq!(correlator.handle(inform.clone()), None);
+        let wrong_name = message(MessageType::Reply, b"bar", Some(7));
+        assert_eq!(
+            correlator.handle(wrong_name.clone()),
+            Some(Correlation::Unmatched(wrong_name))
+        );
+        let no_mid = message(MessageType::Reply, b"foo", None);
+        assert_eq!(
+            correlator.handle(no_mid.clone()),
+            Some(Correlation::Unmatched(no_mid))
+        );
+        assert_eq!(correlator.len(), 1);
+
+        let reply = message(MessageType::Reply, b"foo", Some(7));
+        assert_eq!(
+            correlator.handle(reply.clone()),
+            Some(Correlation::Matched {
+                request,
+                informs: vec![inform],
+                reply: reply.clone()
+            })
+        );
+        assert!(correlator.is_empty());
+        // A second reply no longer matches
+        assert_eq!(
+            correlator.handle(reply.clone()),
+            Some(Correlation::Unmatched(reply))
+        );
+    }
+
+    #[rstest]
+    fn test_interleaved_informs(mut correlator: Correlator<Vec<u8>, Vec<u8>>) {
+        let now = Instant::now();
+        for (name, mid) in [(b"foo", 1), (b"bar", 2)] {
+            correlator
+                .register(message(MessageType::Request, name, Some(mid)), now)
+                .unwrap();
+        }
+        let stream = [
+            message(MessageType::Inform, b"foo", Some(1)),
+            message(MessageType::Inform, b"bar", Some(2)),
+            // These match no request and are ignored
+            message(MessageType::Inform, b"bar", Some(1)),
+            message(MessageType::Inform, b"foo", None),
+            message(MessageType::Inform, b"foo", Some(3)),
+            message(MessageType::Inform, b"bar", Some(2)),
+            message(MessageType::Reply, b"bar", Some(2)),
+            message(MessageType::Reply, b"foo", Some(1)),
+        ];
+        let completed: Vec<_> = stream
+            .into_iter()
+            .filter_map(|msg| correlator.handle(msg))
+            .map(|correlation| match correlation {
+                Correlation::Matched {
+                    request, informs, ..
+                } => (request.mid.unwrap(), informs.len()),
+                Correlation::Unmatched(reply) => panic!("unmatched reply {reply:?}"),
+            })
+            .collect();
+        assert_eq!(completed, vec![(2, 2), (1, 1)]);
+        assert!(correlator.is_empty());
+    }
+
+    #[rstest]
+    #[case(
+        message(MessageType::Reply, b"foo", Some(1)),
+        RegisterError::NotRequest
+    )]
+    #[case(message(MessageType::Request, b"foo", None), RegisterError::MissingId)]
+    #[case(
+        message(MessageType::Request, b"bar", Some(3)),
+        RegisterError::DuplicateId(3)
+    )]
+    fn test_register_error(
+        mut correlator: Correlator<Vec<u8>, Vec<u8>>,
+        #[case] request: OwnedMessage,
+        #[case] expected: RegisterError,
+    ) {
+        let now = Instant::now();
+        correlator
+            .register(message(MessageType::Request, b"foo", Some(3)), now)
+            .unwrap();
+        assert_eq!(correlator.register(request, now), Err(expected));
+        assert_eq!(correlator.len(), 1);
+    }
+
+    #[rstest]
+    fn test_expire(mut correlator: Correlator<Vec<u8>, Vec<u8>>) {
+        let start = Instant::now();
+        let second = Duration::from_secs(1);
+        for (mid, offset) in [(1, 2), (2, 0), (3, 5), (4, 1)] {
+            let request = message(MessageType::Request, b"foo", Some(mid));
+            correlator
+                .register(request, start + offset * second)
+                .unwrap();
+        }
+        let expired: Vec<_> = correlator
+            .expire(start + 4 * second, 2 * second)
+            .into_iter()
+            .map(|request| request.mid.unwrap())
+            .collect();
+        assert_eq!(expired, vec![2, 4, 1]);
+        assert_eq!(correlator.len(), 1);
+    }
+}
@@ -0,0 +1,238 @@
+/* Copyright (c) 2024, National Research Foundation (SARAO)
+ *
+ * Licensed under the BSD 3-Clause License (the "License"); you may not use
+ * this file except in compliance with the License. You may obtain a copy
+ * of the License at
+ *
+ *   https://opensource.org/licenses/BSD-3-Clause
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Encoding of many messages into a single buffer
+
+use std::borrow::Borrow;
+use std::marker::PhantomData;
+
+use crate::message::Message;
+
+/// Message encoder.
+///
+/// Messages are appended to an internal buffer, which can be retrieved once
+/// a batch of messages is complete. This is the counterpart of
+/// [Parser](crate::parse::Parser).
+#[derive(Clone, Debug, Default)]
+pub struct Encoder {
+    buffer: Vec<u8>,
+}
+
+impl Encoder {
+    /// Create an encoder with an empty buffer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an encoder whose buffer can hold `capacity` bytes without
+    /// reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            buffer: Vec::with_capacity(capacity),
+        }
+    }
+
+    /// Append an encoded message to the buffer.
+    pub fn push<N, A>(&mut self, message: &Message<N, A>)
+    where
+        N: AsRef<[u8]>,
+        A: AsRef<[u8]>,
+    {
+        message.extend_vec(&mut self.buffer);
+    }
+
+    /// The messages encoded so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    /// Number of bytes encoded so far.
+    pub fn len(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Whether nothing has been encoded since the last [take](Self::take).
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Return the encoded messages, leaving the encoder empty.
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+}
+
+/// Frame of encoded messages produced by [Framer].
+#[derive(Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct Frame {
+    /// The encoded messages
+    pub data: Vec<u8>,
+    /// Whether the frame is larger than the maximum frame size. This only
+    /// happens when a single message is too large, in which case it is the
+    /// only message in the frame.
+    pub oversized: bool,
+}
+
+/// Iterator adaptor that packs encoded messages into frames of bounded size.
+///
+/// Each frame contains as many consecutive messages as fit into the maximum
+/// frame size, and messages are never split across frames. A message that
+/// is too large by itself is placed in a frame on its own, which is flagged
+/// as [oversized](Frame::oversized).
+pub struct Framer<I, N, A>
+where
+    I: Iterator,
+{
+    messages: I,
+    max_frame_size: usize,
+    /// Message that did not fit into the previous frame
+    pending: Option<I::Item>,
+    _phantom: PhantomData<fn() -> (N, A)>,
+}
+
+impl<I, N, A> Framer<I, N, A>
+where
+    I: Iterator,
+    I::Item: Borrow<Message<N, A>>,
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    /// Create a framer for a sequence of messages (or references to them).
+    pub fn new<T>(messages: T, max_frame_size: usize) -> Self
+    where
+        T: IntoIterator<IntoIter = I>,
+    {
+        Self {
+            messages: messages.into_iter(),
+            max_frame_size,
+            pending: None,
+            _phantom: PhantomData,
+        }
+    }
+}
+
+impl<I, N, A> Iterator for Framer<I, N, A>
+where
+    I: Iterator,
+    I::Item: Borrow<Message<N, A>>,
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    type Item = Frame;
+
+    fn next(&mut self) -> Option<Frame> {
+        let mut frame = Frame::default();
+        while let Some(item) = self.pending.take().or_else(|| self.messages.next()) {
+            let message = item.borrow();
+            let size = message.write_size();
+            if frame.data.is_empty() && size > self.max_frame_size {
+                message.extend_vec(&mut frame.data);
+                frame.oversized = true;
+                return Some(frame);
+            } else if frame.data.len() + size > self.max_frame_size {
+                self.pending = Some(item);
+                break;
+            }
+            message.extend_vec(&mut frame.data);
+        }
+        if frame.data.is_empty() {
+            None
+        } else {
+            Some(frame)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use crate::message::{MessageType, OwnedMessage};
+
+    #[test]
+    fn test_encoder() {
+        let mut encoder = Encoder::with_capacity(4);
+        assert!(encoder.is_empty());
+        let first: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Inform,
+            b"sensor-value".as_slice(),
+            Some(3),
+            vec![b"1.5".as_slice(), b""],
+        );
+        let second: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Reply,
+            b"sensor-value".as_slice(),
+            Some(3),
+            vec![b"ok".as_slice()],
+        );
+        encoder.push(&first);
+        encoder.push(&second);
+        let expected = b"#sensor-value[3] 1.5 \\@\n!sensor-value[3] ok\n";
+        assert_eq!(encoder.as_bytes(), expected);
+        assert_eq!(encoder.len(), expected.len());
+        assert_eq!(encoder.take(), expected);
+        assert!(encoder.is_empty());
+
+        encoder.push(&second);
+        assert_eq!(encoder.as_bytes(), b"!sensor-value[3] ok\n");
+    }
+
+    #[test]
+    fn test_framer() {
+        let request = |name: &str, arg: &str| -> OwnedMessage {
+            Message::new(
+                MessageType::Request,
+                name.as_bytes().to_vec(),
+                None,
+                vec![arg.as_bytes().to_vec()],
+            )
+        };
+        // Encoded sizes are 7, 8, 30, 6 and 6 bytes
+        let messages = vec![
+            request("a", "123"),
+            request("bb", "123"),
+            request("long", "1234567890123456789012"),
+            request("a", "12"),
+            request("b", "34"),
+        ];
+        let frames: Vec<Frame> = Framer::new(&messages, 15).collect();
+        assert_eq!(
+            frames,
+            vec![
+                Frame {
+                    data: b"?a 123\n?bb 123\n".to_vec(),
+                    oversized: false
+                },
+                Frame {
+                    data: b"?long 1234567890123456789012\n".to_vec(),
+                    oversized: true
+                },
+                Frame {
+                    data: b"?a 12\n?b 34\n".to_vec(),
+                    oversized: false
+                },
+            ]
+        );
+
+        // Owned messages, and an empty sequence
+        assert_eq!(
+            Framer::new(messages, 0)
+                .filter(|frame| frame.oversized)
+                .count(),
+            5
+        );
+        assert_eq!(Framer::new(Vec::<OwnedMessage>::new(), 100).next(), None);
+    }
+}
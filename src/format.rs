@@ -13,10 +13,15 @@
  * limitations under the License.
  */
 
+use std::fmt::{self, Write};
+use std::io;
 use std::ops::AddAssign;
+use thiserror::Error;
 use uninit::prelude::*;
 
-use crate::message::{Message, MessageType};
+use crate::message::{Message, MessageError};
+use crate::parse::FieldLocation;
+use crate::scan::find_escape;
 use crate::tables::{ESCAPE_FLAG, ESCAPE_SYMBOL};
 
 // Accumulator that panics on overflow
@@ -31,19 +36,92 @@ impl AddAssign<usize> for Accumulator {
     }
 }
 
+/// Number of consecutive bytes needing no escape after which
+/// [write_out_with](Message::write_out_with) switches from copying one byte
+/// at a time to a vectorised scan.
+const BULK_RUN: usize = 16;
+
+/// Maximum number of decimal digits in a message ID
+const MAX_MESSAGE_ID_DIGITS: usize = 10;
+
+/// Error returned when formatting into a buffer that is too small
+#[derive(Copy, Clone, Debug, Error, Eq, PartialEq, Hash)]
+#[error("buffer is too small ({available} bytes, but {needed} bytes are needed)")]
+pub struct BufferTooSmall {
+    /// Number of bytes needed to hold the message
+    pub needed: usize,
+    /// Number of bytes in the buffer
+    pub available: usize,
+}
+
+/// Line terminator written at the end of a message.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum LineTerminator {
+    /// `\n`
+    #[default]
+    Lf,
+    /// `\r\n`, for peers that expect it. The parser treats the `\r` as
+    /// the end of the line and the `\n` as an empty line.
+    CrLf,
+}
+
+impl LineTerminator {
+    /// The bytes of the terminator.
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            LineTerminator::Lf => b"\n",
+            LineTerminator::CrLf => b"\r\n",
+        }
+    }
+}
+
+/// Custom rules for escaping arguments.
+///
+/// These are intended for diagnostics and for peers with non-standard
+/// requirements: output that does not use the standard rules (see
+/// [EscapeRules::katcp]) may not be parsable by other katcp implementations.
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub struct EscapeRules {
+    /// For each byte, the character to write after a backslash to escape
+    /// it, or zero if the byte is written as-is
+    pub symbols: [u8; 256],
+    /// Whether to write `\@` for empty arguments
+    pub mark_empty: bool,
+}
+
+impl EscapeRules {
+    /// Get the standard katcp escaping rules.
+    pub fn katcp() -> Self {
+        Self {
+            symbols: ESCAPE_SYMBOL.into_array(),
+            mark_empty: true,
+        }
+    }
+}
+
+impl Default for EscapeRules {
+    fn default() -> Self {
+        Self::katcp()
+    }
+}
+
+/// Optional formatting behaviour.
+///
+/// The defaults give the same output as [Message::write_out].
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub struct FormatOptions<'a> {
+    /// Terminator for the line
+    pub terminator: LineTerminator,
+    /// Rules for escaping arguments. If not specified, the standard katcp
+    /// rules are used, which is faster than specifying them explicitly.
+    pub escape: Option<&'a EscapeRules>,
+}
+
 impl<N, A> Message<N, A>
 where
     N: AsRef<[u8]>,
     A: AsRef<[u8]>,
 {
-    fn type_symbol(mtype: MessageType) -> u8 {
-        match mtype {
-            MessageType::Request => b'?',
-            MessageType::Reply => b'!',
-            MessageType::Inform => b'#',
-        }
-    }
-
     /// Write a single byte to `target` and return the remaining suffix.
     ///
     /// # Safety
@@ -79,8 +157,24 @@ where
     ///
     /// This will panic if the target is smaller than the value returned by
     /// [write_size](Self::write_size).
-    pub fn write_out<'a>(&self, mut target: Out<'a, [u8]>) -> Out<'a, [u8]> {
-        target = Self::append_byte(target, Self::type_symbol(self.mtype));
+    pub fn write_out<'a>(&self, target: Out<'a, [u8]>) -> Out<'a, [u8]> {
+        self.write_out_with(target, &FormatOptions::default())
+    }
+
+    /// Write the message into a buffer, with non-default options.
+    ///
+    /// It returns any unused part of the buffer.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the target is smaller than the value returned by
+    /// [write_size_with](Self::write_size_with) for the same options.
+    pub fn write_out_with<'a>(
+        &self,
+        mut target: Out<'a, [u8]>,
+        options: &FormatOptions,
+    ) -> Out<'a, [u8]> {
+        target = Self::append_byte(target, self.mtype.symbol());
         target = Self::append_bytes(target, self.name.as_ref());
         if let Some(mid) = self.mid {
             target = Self::append_byte(target, b'[');
@@ -91,21 +185,95 @@ where
         for argument in self.arguments.iter() {
             let argument = argument.as_ref();
             target = Self::append_byte(target, b' ');
+            if let Some(rules) = options.escape {
+                target = Self::append_escaped_with(target, argument, rules);
+                continue;
+            }
             if argument.is_empty() {
                 target = Self::append_bytes(target, b"\\@");
             }
-            for &c in argument.iter() {
+            // Copy the leading run of bytes that need no escaping in one go.
+            // This is often the whole argument.
+            let mut pos = find_escape(argument);
+            target = Self::append_bytes(target, &argument[..pos]);
+            let mut run = 0; // number of consecutive bytes without escapes
+            while let Some(&c) = argument.get(pos) {
                 let esc = ESCAPE_SYMBOL[c];
+                pos += 1;
                 if esc == 0 {
                     // No escaping is needed
                     target = Self::append_byte(target, c);
+                    run += 1;
+                    if run == BULK_RUN {
+                        // This looks like a long run, so find the rest of
+                        // it with SIMD and copy it in one go.
+                        let len = find_escape(&argument[pos..]);
+                        target = Self::append_bytes(target, &argument[pos..pos + len]);
+                        pos += len;
+                        run = 0;
+                    }
                 } else {
                     target = Self::append_byte(target, b'\\');
                     target = Self::append_byte(target, esc);
+                    run = 0;
                 }
             }
         }
-        Self::append_byte(target, b'\n')
+        Self::append_bytes(target, options.terminator.as_bytes())
+    }
+
+    /// Write `argument` to `target`, escaped according to custom rules.
+    fn append_escaped_with<'a>(
+        mut target: Out<'a, [u8]>,
+        argument: &[u8],
+        rules: &EscapeRules,
+    ) -> Out<'a, [u8]> {
+        if argument.is_empty() && rules.mark_empty {
+            target = Self::append_bytes(target, b"\\@");
+        }
+        for &c in argument.iter() {
+            let esc = rules.symbols[c as usize];
+            if esc == 0 {
+                target = Self::append_byte(target, c);
+            } else {
+                target = Self::append_byte(target, b'\\');
+                target = Self::append_byte(target, esc);
+            }
+        }
+        target
+    }
+
+    /// Create a [MessageWriter] to format the message in pieces.
+    pub fn writer(&self) -> MessageWriter<'_, N, A> {
+        MessageWriter::new(self)
+    }
+
+    /// Write the message to the start of a slice.
+    ///
+    /// This does not allocate. On success, the number of bytes written is
+    /// returned. If the slice is too small (see
+    /// [write_size](Self::write_size)), it is left unchanged and an error is
+    /// returned.
+    pub fn write_slice(&self, buffer: &mut [u8]) -> Result<usize, BufferTooSmall> {
+        self.write_slice_with(buffer, &FormatOptions::default())
+    }
+
+    /// Write the message to the start of a slice, with non-default options.
+    ///
+    /// See [write_slice](Self::write_slice) for details.
+    pub fn write_slice_with(
+        &self,
+        buffer: &mut [u8],
+        options: &FormatOptions,
+    ) -> Result<usize, BufferTooSmall> {
+        let needed = self.write_size_with(options);
+        let available = buffer.len();
+        if available < needed {
+            return Err(BufferTooSmall { needed, available });
+        }
+        let remain = self.write_out_with(buffer[..needed].as_out(), options);
+        debug_assert!(remain.is_empty());
+        Ok(needed)
     }
 
     /// Get the number of bytes needed by [write_out](Self::write_out).
@@ -114,7 +282,17 @@ where
     ///
     /// This function will panic if the size overflows [usize].
     pub fn write_size(&self) -> usize {
-        let mut bytes = Accumulator(2); // name and newline
+        self.write_size_with(&FormatOptions::default())
+    }
+
+    /// Get the number of bytes needed by [write_out_with](Self::write_out_with).
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the size overflows [usize].
+    pub fn write_size_with(&self, options: &FormatOptions) -> usize {
+        let mut bytes = Accumulator(1); // type
+        bytes += options.terminator.as_bytes().len();
         bytes += self.name.as_ref().len();
         bytes += self.arguments.len(); // spaces between arguments
         if let Some(mid) = self.mid {
@@ -124,39 +302,766 @@ where
         }
         for argument in self.arguments.iter() {
             let argument = argument.as_ref();
+            if let Some(rules) = options.escape {
+                if argument.is_empty() && rules.mark_empty {
+                    bytes += 2; // For the \@
+                }
+                bytes += argument.len();
+                bytes += argument
+                    .iter()
+                    .filter(|&&c| rules.symbols[c as usize] != 0)
+                    .count();
+            } else if argument.is_empty() {
+                bytes += 2; // For the \@
+            } else {
+                bytes += argument.len();
+                bytes += argument.iter().filter(|&&c| ESCAPE_FLAG[c]).count();
+            }
+        }
+        bytes.0
+    }
+
+    /// Check that the message is valid (see [validate](Self::validate))
+    /// and that, once formatted, it will be accepted by a
+    /// [Parser](crate::parse::Parser) with the given maximum line length.
+    ///
+    /// If the line is too long, the error gives the field in which the
+    /// limit is exceeded. The terminator counts as part of the last field.
+    pub fn validate_line_length(&self, max_line_length: usize) -> Result<(), MessageError> {
+        self.validate()?;
+        match self.exceeds_line_length(max_line_length) {
+            Some(location) => Err(MessageError::LineTooLong {
+                location,
+                max_line_length,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether [write_size](Self::write_size) is at most `max_line_length`.
+    ///
+    /// This stops examining the message as soon as the limit is exceeded,
+    /// so it is cheaper than [write_size](Self::write_size) for messages
+    /// that are much larger than the limit.
+    pub fn fits(&self, max_line_length: usize) -> bool {
+        self.exceeds_line_length(max_line_length).is_none()
+    }
+
+    /// Find the field in which the formatted message exceeds
+    /// `max_line_length`, if any.
+    fn exceeds_line_length(&self, max_line_length: usize) -> Option<FieldLocation> {
+        // Include the type and terminator up front, so that exceeding the
+        // limit in the terminator is attributed to the last field.
+        let mut bytes = 2usize.saturating_add(self.name.as_ref().len());
+        if bytes > max_line_length {
+            return Some(FieldLocation::Name);
+        }
+        if let Some(mid) = self.mid {
+            bytes += 2 + itoa::Buffer::new().format(mid).len();
+            if bytes > max_line_length {
+                return Some(FieldLocation::Id);
+            }
+        }
+        for (i, argument) in self.arguments.iter().enumerate() {
+            let argument = argument.as_ref();
+            bytes += 1; // separator
             if argument.is_empty() {
                 bytes += 2; // For the \@
+            } else if argument.len() > max_line_length - bytes.min(max_line_length) {
+                // Escapes can only make it longer, so don't count them
+                return Some(FieldLocation::Argument(i));
             } else {
                 bytes += argument.len();
                 bytes += argument.iter().filter(|&&c| ESCAPE_FLAG[c]).count();
             }
+            if bytes > max_line_length {
+                return Some(FieldLocation::Argument(i));
+            }
+        }
+        None
+    }
+
+    /// Get an upper bound on [write_size](Self::write_size), without
+    /// examining the argument contents.
+    ///
+    /// # Panics
+    ///
+    /// This function will panic if the size overflows [usize].
+    fn max_write_size(&self) -> usize {
+        let mut bytes = Accumulator(1); // type
+        bytes += 1; // newline
+        bytes += self.name.as_ref().len();
+        bytes += self.arguments.len(); // spaces between arguments
+        if self.mid.is_some() {
+            bytes += 2 + MAX_MESSAGE_ID_DIGITS; // 2 for the brackets
+        }
+        for argument in self.arguments.iter() {
+            // Each byte needs at most 2 bytes, and an empty argument needs 2
+            bytes += std::cmp::max(2, argument.as_ref().len());
+            bytes += argument.as_ref().len();
         }
         bytes.0
     }
 
+    /// Write the message to a [Write](io::Write) target.
+    ///
+    /// The output is identical to that of [to_vec](Self::to_vec), but it is
+    /// written directly without building the whole message in memory.
+    /// Unescaped runs of bytes are passed to the writer in a single call, so
+    /// a buffered writer is recommended if there are many escape sequences.
+    ///
+    /// On success, the number of bytes written is returned. Errors from the
+    /// writer are returned as-is, in which case part of the message may have
+    /// been written.
+    pub fn write_to<W: io::Write + ?Sized>(&self, w: &mut W) -> io::Result<usize> {
+        let mut bytes = 0;
+        let mut write = |data: &[u8]| -> io::Result<()> {
+            w.write_all(data)?;
+            bytes += data.len();
+            Ok(())
+        };
+        write(&[self.mtype.symbol()])?;
+        write(self.name.as_ref())?;
+        if let Some(mid) = self.mid {
+            let mut buffer = itoa::Buffer::new();
+            write(b"[")?;
+            write(buffer.format(mid).as_bytes())?;
+            write(b"]")?;
+        }
+        for argument in self.arguments.iter() {
+            let argument = argument.as_ref();
+            write(b" ")?;
+            if argument.is_empty() {
+                write(b"\\@")?;
+            }
+            let mut rest = argument;
+            loop {
+                let pos = find_escape(rest);
+                if pos == rest.len() {
+                    break;
+                }
+                write(&rest[..pos])?;
+                write(&[b'\\', ESCAPE_SYMBOL[rest[pos]]])?;
+                rest = &rest[pos + 1..];
+            }
+            write(rest)?;
+        }
+        write(b"\n")?;
+        Ok(bytes)
+    }
+
+    /// Append `value` to `out` in the form used by [to_log_string](Self::to_log_string).
+    fn append_log_escaped(out: &mut String, value: &[u8]) {
+        for &c in value.iter() {
+            let esc = ESCAPE_SYMBOL[c];
+            if esc != 0 {
+                out.push('\\');
+                out.push(esc as char);
+            } else if c.is_ascii_graphic() {
+                out.push(c as char);
+            } else {
+                // Writing to a String cannot fail
+                write!(out, "\\x{c:02x}").unwrap();
+            }
+        }
+    }
+
+    /// Render the message as an ASCII string suitable for logging.
+    ///
+    /// This matches the wire format, except that the trailing newline is
+    /// omitted, and bytes which are not printable ASCII and which do not
+    /// have a katcp escape sequence are written as `\xNN` (with two
+    /// lowercase hex digits). Since backslashes are always escaped, the
+    /// result is unambiguous.
+    pub fn to_log_string(&self) -> String {
+        let mut out = String::with_capacity(self.write_size());
+        Self::append_log_escaped(&mut out, &[self.mtype.symbol()]);
+        Self::append_log_escaped(&mut out, self.name.as_ref());
+        if let Some(mid) = self.mid {
+            out.push('[');
+            let mut buffer = itoa::Buffer::new();
+            out.push_str(buffer.format(mid));
+            out.push(']');
+        }
+        for argument in self.arguments.iter() {
+            let argument = argument.as_ref();
+            out.push(' ');
+            if argument.is_empty() {
+                out.push_str("\\@");
+            }
+            Self::append_log_escaped(&mut out, argument);
+        }
+        out
+    }
+
     /// Encode the message to a [Vec]
     pub fn to_vec(&self) -> Vec<u8> {
+        let mut vec = Vec::new();
+        self.extend_vec(&mut vec);
+        vec
+    }
+
+    /// Encode the message to a [Vec], traversing the arguments only once.
+    ///
+    /// See [extend_vec_single_pass](Self::extend_vec_single_pass) for the
+    /// trade-offs.
+    pub fn to_vec_single_pass(&self) -> Vec<u8> {
+        let mut vec = Vec::new();
+        self.extend_vec_single_pass(&mut vec);
+        vec
+    }
+
+    /// Append the encoded message to the end of an existing [Vec],
+    /// traversing the arguments only once.
+    ///
+    /// The result is the same as for [extend_vec](Self::extend_vec), but
+    /// instead of first computing the exact size, space is reserved for the
+    /// worst case (every byte of every argument being escaped). This is
+    /// faster for messages with many or long arguments, at the cost of up to
+    /// twice as much spare capacity in `out`.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the worst-case size of the message overflows
+    /// [usize], or if the resulting length of `out` would.
+    pub fn extend_vec_single_pass(&self, out: &mut Vec<u8>) {
+        let max_size = self.max_write_size();
+        let remain = self.write_out(out.reserve_uninit(max_size).as_out());
+        let size = max_size - remain.len();
+        // SAFETY: write_out initialized all but the remaining elements
+        // of the reserved space.
+        unsafe {
+            out.set_len(out.len() + size);
+        }
+    }
+
+    /// Append the encoded message to the end of an existing [Vec].
+    ///
+    /// The existing contents are left unchanged. At most one reallocation
+    /// is done.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if the size of the message overflows [usize] (see
+    /// [write_size](Self::write_size)), or if the resulting length of
+    /// `out` would.
+    pub fn extend_vec(&self, out: &mut Vec<u8>) {
         let size = self.write_size();
-        let mut vec = Vec::with_capacity(size);
-        let remain = self.write_out(vec.get_backing_buffer());
+        let remain = self.write_out(out.reserve_uninit(size).as_out());
         if !remain.is_empty() {
             panic!("Size of message changed during formatting.");
         }
-        // SAFETY: we've verified that write_out initialized all elements.
+        // SAFETY: we've verified that write_out initialized all the
+        // reserved elements.
         unsafe {
-            vec.set_len(size);
+            out.set_len(out.len() + size);
+        }
+    }
+}
+
+/// Part of the message that [MessageWriter] is up to.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum WriterField {
+    Type,
+    Name,
+    Id,
+    /// Separator before the argument with the given index (and `\@` if
+    /// the argument is empty), or the terminator if there are no more
+    /// arguments
+    Separator(usize),
+    Argument(usize),
+    Terminator,
+    Done,
+}
+
+/// Formats a message in pieces, into buffers supplied by the caller.
+///
+/// This produces the same bytes as [Message::to_vec], but it does not
+/// need a buffer large enough for the whole message, and does not allocate.
+/// It is created by [Message::writer].
+#[derive(Debug)]
+pub struct MessageWriter<'a, N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    message: &'a Message<N, A>,
+    field: WriterField,
+    /// Number of bytes of the current field (before escaping) already
+    /// written, for the name and arguments
+    offset: usize,
+    /// Short fixed pieces (such as the message ID or an escape sequence)
+    /// still to be written before continuing with `field`
+    staged: [u8; MAX_MESSAGE_ID_DIGITS + 2],
+    staged_start: usize,
+    staged_end: usize,
+}
+
+impl<'a, N, A> MessageWriter<'a, N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    /// Start writing `message`.
+    pub fn new(message: &'a Message<N, A>) -> Self {
+        Self {
+            message,
+            field: WriterField::Type,
+            offset: 0,
+            staged: [0; MAX_MESSAGE_ID_DIGITS + 2],
+            staged_start: 0,
+            staged_end: 0,
+        }
+    }
+
+    fn stage(&mut self, data: &[u8]) {
+        self.staged[..data.len()].copy_from_slice(data);
+        self.staged_start = 0;
+        self.staged_end = data.len();
+    }
+
+    /// Write as much of the message as fits into `out`.
+    ///
+    /// Returns the number of bytes written to the start of `out`, and
+    /// whether the message is now complete. Once it is complete, further
+    /// calls write nothing.
+    pub fn write_some(&mut self, out: &mut [u8]) -> (usize, bool) {
+        let mut written = 0;
+        loop {
+            if self.staged_start < self.staged_end {
+                let n = (self.staged_end - self.staged_start).min(out.len() - written);
+                out[written..written + n]
+                    .copy_from_slice(&self.staged[self.staged_start..self.staged_start + n]);
+                written += n;
+                self.staged_start += n;
+                if self.staged_start < self.staged_end {
+                    return (written, false);
+                }
+            }
+            let space = out.len() - written;
+            match self.field {
+                WriterField::Type => {
+                    self.stage(&[self.message.mtype.symbol()]);
+                    self.field = WriterField::Name;
+                }
+                WriterField::Name => {
+                    let rest = &self.message.name.as_ref()[self.offset..];
+                    let n = rest.len().min(space);
+                    out[written..written + n].copy_from_slice(&rest[..n]);
+                    written += n;
+                    self.offset += n;
+                    if n < rest.len() {
+                        return (written, false);
+                    }
+                    self.field = WriterField::Id;
+                }
+                WriterField::Id => {
+                    if let Some(mid) = self.message.mid {
+                        let mut buffer = itoa::Buffer::new();
+                        let digits = buffer.format(mid).as_bytes();
+                        self.staged[0] = b'[';
+                        self.staged[1..=digits.len()].copy_from_slice(digits);
+                        self.staged[digits.len() + 1] = b']';
+                        self.staged_start = 0;
+                        self.staged_end = digits.len() + 2;
+                    }
+                    self.field = WriterField::Separator(0);
+                }
+                WriterField::Separator(index) => {
+                    self.offset = 0;
+                    self.field = WriterField::Argument(index);
+                    match self.message.arguments.get(index) {
+                        Some(argument) if argument.as_ref().is_empty() => self.stage(b" \\@"),
+                        Some(_) => self.stage(b" "),
+                        None => self.field = WriterField::Terminator,
+                    }
+                }
+                WriterField::Argument(index) => {
+                    let rest = &self.message.arguments[index].as_ref()[self.offset..];
+                    let pos = find_escape(rest);
+                    let n = pos.min(space);
+                    out[written..written + n].copy_from_slice(&rest[..n]);
+                    written += n;
+                    self.offset += n;
+                    if n < pos {
+                        return (written, false);
+                    }
+                    match rest.get(pos) {
+                        Some(&c) => {
+                            self.stage(&[b'\\', ESCAPE_SYMBOL[c]]);
+                            self.offset += 1;
+                        }
+                        None => self.field = WriterField::Separator(index + 1),
+                    }
+                }
+                WriterField::Terminator => {
+                    self.stage(b"\n");
+                    self.field = WriterField::Done;
+                }
+                WriterField::Done => return (written, true),
+            }
         }
-        vec
+    }
+}
+
+/// Escape a single argument value, as it would appear in a message.
+///
+/// The escaped form is appended to `out`. An empty value is written as
+/// `\@`. See [unescape_argument](crate::parse::unescape_argument) for the
+/// inverse.
+pub fn escape_argument(raw: &[u8], out: &mut Vec<u8>) {
+    if raw.is_empty() {
+        out.extend_from_slice(b"\\@");
+        return;
+    }
+    let mut rest = raw;
+    loop {
+        let pos = find_escape(rest);
+        out.extend_from_slice(&rest[..pos]);
+        match rest.get(pos) {
+            Some(&c) => {
+                out.extend_from_slice(&[b'\\', ESCAPE_SYMBOL[c]]);
+                rest = &rest[pos + 1..];
+            }
+            None => break,
+        }
+    }
+}
+
+/// Write `value` in the form used by the [Display](fmt::Display) implementation.
+fn write_display_escaped(f: &mut impl fmt::Write, mut value: &[u8]) -> fmt::Result {
+    fn write_hex(f: &mut impl fmt::Write, bytes: &[u8]) -> fmt::Result {
+        for c in bytes.iter() {
+            write!(f, "\\x{c:02x}")?;
+        }
+        Ok(())
+    }
+
+    while !value.is_empty() {
+        let (valid, invalid) = match std::str::from_utf8(value) {
+            Ok(valid) => (valid, &[][..]),
+            Err(err) => {
+                let (valid, rest) = value.split_at(err.valid_up_to());
+                let invalid_len = err.error_len().unwrap_or(rest.len());
+                // SAFETY: from_utf8 has validated this prefix
+                let valid = unsafe { std::str::from_utf8_unchecked(valid) };
+                (valid, &rest[..invalid_len])
+            }
+        };
+        for ch in valid.chars() {
+            let mut buffer = [0u8; 4];
+            let encoded = ch.encode_utf8(&mut buffer).as_bytes();
+            let esc = ESCAPE_SYMBOL[encoded[0]];
+            if esc != 0 {
+                f.write_char('\\')?;
+                f.write_char(esc as char)?;
+            } else if ch.is_control() {
+                write_hex(f, encoded)?;
+            } else {
+                f.write_char(ch)?;
+            }
+        }
+        write_hex(f, invalid)?;
+        value = &value[valid.len() + invalid.len()..];
+    }
+    Ok(())
+}
+
+/// Render the message for diagnostic purposes.
+///
+/// This matches the wire format, except that the trailing newline is
+/// omitted, and bytes which do not have a katcp escape sequence and which
+/// are not part of a printable UTF-8 character are written as `\xNN`
+/// (with two lowercase hex digits). Use [to_log_string](Message::to_log_string)
+/// for output that is pure ASCII.
+impl<N, A> fmt::Display for Message<N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_char(self.mtype.symbol() as char)?;
+        write_display_escaped(f, self.name.as_ref())?;
+        if let Some(mid) = self.mid {
+            write!(f, "[{mid}]")?;
+        }
+        for argument in self.arguments.iter() {
+            let argument = argument.as_ref();
+            f.write_char(' ')?;
+            if argument.is_empty() {
+                f.write_str("\\@")?;
+            }
+            write_display_escaped(f, argument)?;
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::message::MessageType;
 
     use rstest::*;
     use std::cell::Cell;
 
+    #[test]
+    fn static_name() {
+        let message = Message::with_static_name(
+            MessageType::Reply,
+            b"help",
+            Some(3),
+            vec![b"ok".to_vec(), vec![b' ']],
+        );
+        assert_eq!(message.to_vec(), b"!help[3] ok \\_\n");
+    }
+
+    #[test]
+    fn write_slice() {
+        let message: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Reply,
+            b"help".as_slice(),
+            Some(3),
+            vec![b"ok".as_slice(), b"a b"],
+        );
+        let mut buffer = [0u8; 64];
+        let size = message.write_slice(&mut buffer).unwrap();
+        assert_eq!(&buffer[..size], b"!help[3] ok a\\_b\n");
+        assert!(buffer[size..].iter().all(|&c| c == 0));
+
+        let options = FormatOptions {
+            terminator: LineTerminator::CrLf,
+            ..Default::default()
+        };
+        let size = message.write_slice_with(&mut buffer, &options).unwrap();
+        assert_eq!(&buffer[..size], b"!help[3] ok a\\_b\r\n");
+
+        let mut short = [0u8; 16];
+        assert_eq!(
+            message.write_slice(&mut short),
+            Err(BufferTooSmall {
+                needed: 17,
+                available: 16
+            })
+        );
+        assert_eq!(short, [0u8; 16]);
+    }
+
+    #[rstest]
+    #[case(100, Ok(()))]
+    #[case(20, Ok(()))]
+    #[case(19, Err(FieldLocation::Argument(2)))]
+    #[case(15, Err(FieldLocation::Argument(1)))]
+    #[case(12, Err(FieldLocation::Argument(0)))]
+    #[case(9, Err(FieldLocation::Id))]
+    #[case(5, Err(FieldLocation::Name))]
+    fn validate_line_length(
+        #[case] max_line_length: usize,
+        #[case] expected: Result<(), FieldLocation>,
+    ) {
+        let message: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Request,
+            b"help".as_slice(),
+            Some(12),
+            vec![b"a b".as_slice(), b"", b"c"],
+        );
+        assert_eq!(message.write_size(), 20);
+        assert_eq!(
+            message.validate_line_length(max_line_length),
+            expected.map_err(|location| MessageError::LineTooLong {
+                location,
+                max_line_length
+            })
+        );
+        assert_eq!(message.fits(max_line_length), expected.is_ok());
+        let invalid: Message<&[u8], &[u8]> =
+            Message::new(MessageType::Request, b"".as_slice(), None, vec![]);
+        assert!(matches!(
+            invalid.validate_line_length(100),
+            Err(MessageError::Name(_))
+        ));
+    }
+
+    #[test]
+    fn writer() {
+        let message: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Inform,
+            b"log".as_slice(),
+            Some(12),
+            vec![b"".as_slice(), b"a\nb"],
+        );
+        let mut writer = message.writer();
+        let mut buffer = [0u8; 5];
+        assert_eq!(writer.write_some(&mut []), (0, false));
+        assert_eq!(writer.write_some(&mut buffer), (5, false));
+        assert_eq!(&buffer, b"#log[");
+        assert_eq!(writer.write_some(&mut buffer), (5, false));
+        assert_eq!(&buffer, b"12] \\");
+        assert_eq!(writer.write_some(&mut buffer), (5, false));
+        assert_eq!(&buffer, b"@ a\\n");
+        assert_eq!(writer.write_some(&mut buffer), (2, true));
+        assert_eq!(&buffer[..2], b"b\n");
+        assert_eq!(writer.write_some(&mut buffer), (0, true));
+    }
+
+    #[rstest]
+    #[case(LineTerminator::Lf, b"?help[1] a\n".as_slice())]
+    #[case(LineTerminator::CrLf, b"?help[1] a\r\n".as_slice())]
+    fn line_terminator(#[case] terminator: LineTerminator, #[case] expected: &[u8]) {
+        let message: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Request,
+            b"help".as_slice(),
+            Some(1),
+            vec![b"a".as_slice()],
+        );
+        let options = FormatOptions {
+            terminator,
+            ..Default::default()
+        };
+        assert_eq!(message.write_size_with(&options), expected.len());
+        let mut buffer = vec![0; expected.len()];
+        let remain = message.write_out_with(buffer.as_mut_slice().as_out(), &options);
+        assert!(remain.is_empty());
+        assert_eq!(buffer, expected);
+    }
+
+    #[rstest]
+    #[case(MessageType::Request, Some(1), &["foo bar"], r"?help[1] foo\_bar")]
+    #[case(MessageType::Reply, None, &["ok", ""], r"!help ok \@")]
+    #[case(MessageType::Inform, None, &["caf\u{e9}\t\\"], r"#help café\t\\")]
+    #[case(MessageType::Inform, None, &["\x01\u{85}"], r"#help \x01\xc2\x85")]
+    fn display(
+        #[case] mtype: MessageType,
+        #[case] mid: Option<u32>,
+        #[case] arguments: &[&str],
+        #[case] expected: &str,
+    ) {
+        let arguments: Vec<_> = arguments.iter().map(|arg| arg.as_bytes()).collect();
+        let message: Message<&[u8], &[u8]> =
+            Message::new(mtype, b"help".as_slice(), mid, arguments);
+        assert_eq!(message.to_string(), expected);
+    }
+
+    #[test]
+    fn display_invalid_utf8() {
+        let message: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Inform,
+            b"help".as_slice(),
+            None,
+            vec![b"\xffa\xe2\x82".as_slice(), b"ok\xe2\x82"],
+        );
+        assert_eq!(message.to_string(), r"#help \xffa\xe2\x82 ok\xe2\x82");
+    }
+
+    #[rstest]
+    #[case(Some(u32::MAX), vec![])]
+    #[case(None, vec![b"".as_slice()])]
+    #[case(Some(1), vec![b"\\\\".as_slice(), b"abc", b""])]
+    fn single_pass(#[case] mid: Option<u32>, #[case] arguments: Vec<&[u8]>) {
+        let message: Message<&[u8], &[u8]> =
+            Message::new(MessageType::Request, b"help".as_slice(), mid, arguments);
+        let expected = message.to_vec();
+        assert!(message.max_write_size() >= expected.len());
+        assert_eq!(message.to_vec_single_pass(), expected);
+        let mut out = b"prefix".to_vec();
+        message.extend_vec_single_pass(&mut out);
+        assert_eq!(out, [b"prefix".as_slice(), &expected].concat());
+    }
+
+    #[test]
+    fn escape_rules() {
+        let message: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Inform,
+            b"test".as_slice(),
+            None,
+            vec![b"a=b c".as_slice(), b""],
+        );
+        let mut rules = EscapeRules::katcp();
+        let options = FormatOptions {
+            escape: Some(&rules),
+            ..Default::default()
+        };
+        let mut buffer = [0u8; 64];
+        let size = message.write_slice_with(&mut buffer, &options).unwrap();
+        assert_eq!(&buffer[..size], message.to_vec());
+
+        rules.symbols[b'=' as usize] = b'=';
+        rules.mark_empty = false;
+        let options = FormatOptions {
+            escape: Some(&rules),
+            ..Default::default()
+        };
+        let expected = b"#test a\\=b\\_c \n";
+        assert_eq!(message.write_size_with(&options), expected.len());
+        let size = message.write_slice_with(&mut buffer, &options).unwrap();
+        assert_eq!(&buffer[..size], expected);
+    }
+
+    #[test]
+    fn log_string() {
+        let message: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Inform,
+            b"binary".as_slice(),
+            Some(5),
+            vec![
+                b"hello".as_slice(),
+                b"",
+                b"a\tb c\\\0\x1b\r\n",
+                b"\x7f\x80\xff\x01~",
+            ],
+        );
+        assert_eq!(
+            message.to_log_string(),
+            r"#binary[5] hello \@ a\tb\_c\\\0\e\r\n \x7f\x80\xff\x01~"
+        );
+    }
+
+    #[rstest]
+    #[case(Message::new(MessageType::Request, b"hello".as_slice(), None, vec![]))]
+    #[case(Message::new(
+        MessageType::Inform,
+        b"test-mid".as_slice(),
+        Some(2147483647),
+        vec![b"".as_slice(), b"\r\n\t\x1B\0\\ ", b"plain", b"mixed \\escapes\n"],
+    ))]
+    fn write_to(#[case] message: Message<&[u8], &[u8]>) {
+        let mut out = vec![];
+        let bytes = message.write_to(&mut out).unwrap();
+        assert_eq!(out, message.to_vec());
+        assert_eq!(bytes, out.len());
+    }
+
+    #[test]
+    fn extend_vec() {
+        let first: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Request,
+            b"hello".as_slice(),
+            Some(1),
+            vec![b"a b".as_slice()],
+        );
+        let second: Message<&[u8], &[u8]> =
+            Message::new(MessageType::Reply, b"bye".as_slice(), None, vec![]);
+        let mut out = b"prefix".to_vec();
+        first.extend_vec(&mut out);
+        second.extend_vec(&mut out);
+        assert_eq!(out, b"prefix?hello[1] a\\_b\n!bye\n");
+    }
+
+    #[test]
+    fn write_to_error() {
+        let message: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Request,
+            b"hello".as_slice(),
+            None,
+            vec![b"world".as_slice()],
+        );
+        let mut buffer = [0u8; 8];
+        let mut target = buffer.as_mut_slice();
+        let err = message.write_to(&mut target).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::WriteZero);
+        assert_eq!(&buffer, b"?hello w");
+    }
+
     /// Create a Message that requires more than usize bytes.
     #[test]
     #[should_panic(expected = "message size should not exceed usize::MAX")]
@@ -176,6 +1081,7 @@ mod test {
         // loop, but debug builds do not). Since there is no memory to
         // initialize, set_len should be safe.
         let mut arguments = vec![];
+        #[allow(clippy::uninit_vec)]
         unsafe {
             arguments.set_len(usize::MAX - 5);
         }
@@ -210,7 +1116,7 @@ mod test {
                 // Change by delta every call
                 let cur = self.length.get();
                 self.length.set(cur + self.delta);
-                return &[b'x'; 10000][..cur as usize];
+                &[b'x'; 10000][..cur as usize]
             }
         }
 
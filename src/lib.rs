@@ -16,9 +16,17 @@
 #![doc = include_str!("../README.md")]
 
 mod binding;
+pub mod correlate;
+pub mod encode;
 pub mod format;
 pub mod message;
 pub mod parse;
+pub mod reply;
+mod scan;
+#[cfg(feature = "serde")]
+mod serde_support;
 mod tables;
 #[cfg(test)]
 mod test;
+pub mod typed;
+pub mod types;
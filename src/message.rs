@@ -15,16 +15,120 @@
 
 //! The basic katcp message type
 
-use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::exceptions::{PyIndexError, PyRuntimeError, PyTypeError, PyValueError};
 use pyo3::gc::PyVisit;
 use pyo3::prelude::*;
 use pyo3::pybacked::PyBackedBytes;
-use pyo3::types::{PyBytes, PyList};
+use pyo3::types::{PyByteArray, PyBytes, PyList, PyString};
 use pyo3::PyTraverseError;
 use std::borrow::Cow;
+use thiserror::Error;
 use uninit::prelude::*;
 
-pub use katcp_codec_fsm::MessageType;
+use crate::parse::FieldLocation;
+
+pub use katcp_codec_fsm::{MessageType, UnknownMessageType};
+
+/// A set of [MessageType]s.
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct MessageTypeSet(u8);
+
+impl MessageTypeSet {
+    fn bit(mtype: MessageType) -> u8 {
+        match mtype {
+            MessageType::Request => 1,
+            MessageType::Reply => 2,
+            MessageType::Inform => 4,
+        }
+    }
+
+    /// The set containing no message types.
+    pub fn empty() -> Self {
+        Self(0)
+    }
+
+    /// The set containing all the message types.
+    pub fn all() -> Self {
+        Self(7)
+    }
+
+    /// Add a message type to the set.
+    pub fn insert(&mut self, mtype: MessageType) {
+        self.0 |= Self::bit(mtype);
+    }
+
+    /// Whether the set contains a message type.
+    pub fn contains(&self, mtype: MessageType) -> bool {
+        self.0 & Self::bit(mtype) != 0
+    }
+}
+
+impl From<MessageType> for MessageTypeSet {
+    fn from(mtype: MessageType) -> Self {
+        Self(Self::bit(mtype))
+    }
+}
+
+impl FromIterator<MessageType> for MessageTypeSet {
+    fn from_iter<T: IntoIterator<Item = MessageType>>(iter: T) -> Self {
+        let mut set = Self::empty();
+        for mtype in iter {
+            set.insert(mtype);
+        }
+        set
+    }
+}
+
+/// Largest message ID accepted by the parser.
+pub const MAX_MESSAGE_ID: u32 = i32::MAX as u32;
+
+/// Error returned when a message name is not valid.
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum NameError {
+    #[error("name is empty")]
+    Empty,
+    #[error("invalid character {byte:#04x} in name at position {position}")]
+    InvalidByte { position: usize, byte: u8 },
+}
+
+/// Check that a message name is valid.
+///
+/// This applies the same rule as the parser: the name must be non-empty,
+/// start with an ASCII letter, and contain only ASCII letters, digits and
+/// hyphens. The error gives the position of the first invalid byte.
+pub fn validate_name(name: &[u8]) -> Result<(), NameError> {
+    let first = *name.first().ok_or(NameError::Empty)?;
+    if !first.is_ascii_alphabetic() {
+        return Err(NameError::InvalidByte {
+            position: 0,
+            byte: first,
+        });
+    }
+    match name
+        .iter()
+        .enumerate()
+        .find(|(_, &c)| !(c.is_ascii_alphanumeric() || c == b'-'))
+    {
+        Some((position, &byte)) => Err(NameError::InvalidByte { position, byte }),
+        None => Ok(()),
+    }
+}
+
+/// Error returned by [Message::validate].
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum MessageError {
+    #[error(transparent)]
+    Name(#[from] NameError),
+    #[error("message ID {0} is not in the range [1, {MAX_MESSAGE_ID}]")]
+    InvalidId(u32),
+    /// See [Message::validate_line_length]
+    #[error("line is longer than {max_line_length} bytes (at {location:?})")]
+    LineTooLong {
+        /// The field in which the limit was exceeded
+        location: FieldLocation,
+        max_line_length: usize,
+    },
+}
 
 /// A katcp message. The name and arguments can either own their data or
 /// reference existing data from a buffer.
@@ -36,7 +140,8 @@ pub use katcp_codec_fsm::MessageType;
 ///
 /// The message ID and name are *not* validated when constructed with
 /// [Message::new]. Using an invalid value for either will not panic, but
-/// will lead to invalid formatting from [Message::write_out].
+/// will lead to invalid formatting from [Message::write_out]. Use
+/// [Message::try_new] or [Message::validate] to check them.
 #[derive(Clone, Eq, Debug)]
 pub struct Message<N, A>
 where
@@ -53,6 +158,24 @@ where
     pub arguments: Vec<A>,
 }
 
+/// Split `arg` at the first `=`.
+fn split_kv(arg: &[u8]) -> Option<(&[u8], &[u8])> {
+    let pos = arg.iter().position(|&c| c == b'=')?;
+    Some((&arg[..pos], &arg[pos + 1..]))
+}
+
+/// A [Message] that owns all its data.
+pub type OwnedMessage = Message<Vec<u8>, Vec<u8>>;
+
+/// A [Message] with a borrowed name (typically a literal) and owned arguments.
+pub type StaticNamed<'a> = Message<&'a [u8], Vec<u8>>;
+
+/// A [Message] whose fields may each be borrowed or owned.
+///
+/// This is the type produced by [Parser](crate::parse::Parser). Use
+/// [into_owned](Message::into_owned) to detach it from the parsed data.
+pub type CowMessage<'a> = Message<Cow<'a, [u8]>, Cow<'a, [u8]>>;
+
 impl<N, A, N2, A2> PartialEq<Message<N2, A2>> for Message<N, A>
 where
     N: AsRef<[u8]> + PartialEq<N2>,
@@ -87,17 +210,289 @@ where
             arguments: arguments.into(),
         }
     }
+
+    /// Create a new message, checking that the name and message ID are valid.
+    pub fn try_new(
+        mtype: MessageType,
+        name: impl Into<N>,
+        mid: Option<u32>,
+        arguments: impl Into<Vec<A>>,
+    ) -> Result<Self, MessageError> {
+        let message = Self::new(mtype, name, mid, arguments);
+        message.validate()?;
+        Ok(message)
+    }
+
+    /// Check that the name and message ID can be formatted and parsed
+    /// back. The arguments are always valid.
+    pub fn validate(&self) -> Result<(), MessageError> {
+        validate_name(self.name.as_ref())?;
+        match self.mid {
+            Some(mid) if !(1..=MAX_MESSAGE_ID).contains(&mid) => Err(MessageError::InvalidId(mid)),
+            _ => Ok(()),
+        }
+    }
+
+    /// Create a new message whose arguments are alternating keys and values.
+    pub fn from_pairs<K, V>(
+        mtype: MessageType,
+        name: impl Into<N>,
+        mid: Option<u32>,
+        pairs: impl IntoIterator<Item = (K, V)>,
+    ) -> Self
+    where
+        K: Into<A>,
+        V: Into<A>,
+    {
+        let arguments: Vec<A> = pairs
+            .into_iter()
+            .flat_map(|(key, value)| [key.into(), value.into()])
+            .collect();
+        Self::new(mtype, name, mid, arguments)
+    }
+
+    /// Interpret the arguments as alternating keys and values.
+    ///
+    /// This is the inverse of [Message::from_pairs]. If there is an odd
+    /// number of arguments, [None] is returned (rather than ignoring the
+    /// final argument).
+    pub fn pairs(&self) -> Option<impl Iterator<Item = (&[u8], &[u8])>> {
+        let pairs = self.arguments.chunks_exact(2);
+        if !pairs.remainder().is_empty() {
+            return None;
+        }
+        Some(pairs.map(|pair| (pair[0].as_ref(), pair[1].as_ref())))
+    }
+
+    /// Split argument `index` at its first `=` into a key and value.
+    ///
+    /// Returns [None] if there is no such argument or it contains no `=`.
+    /// Any further `=` signs form part of the value.
+    pub fn arg_kv(&self, index: usize) -> Option<(&[u8], &[u8])> {
+        split_kv(self.arguments.get(index)?.as_ref())
+    }
+
+    /// Split every argument into a key and value, as for [Message::arg_kv].
+    ///
+    /// If any argument contains no `=`, [None] is returned (rather than
+    /// ignoring that argument).
+    pub fn kv_map(&self) -> Option<impl Iterator<Item = (&[u8], &[u8])>> {
+        if !self
+            .arguments
+            .iter()
+            .all(|arg| arg.as_ref().contains(&b'='))
+        {
+            return None;
+        }
+        Some(
+            self.arguments
+                .iter()
+                .map(|arg| split_kv(arg.as_ref()).unwrap()),
+        )
+    }
+
+    /// Whether the message has no arguments at all.
+    ///
+    /// Note that this is not the same as having a single empty argument
+    /// (encoded as `\@`).
+    pub fn is_empty_args(&self) -> bool {
+        self.arguments.is_empty()
+    }
+
+    /// Get the name as a string.
+    ///
+    /// Names produced by the parser (or accepted by [validate_name]) are
+    /// always ASCII, so this only fails for messages constructed with an
+    /// invalid name.
+    pub fn name_str(&self) -> Result<&str, std::str::Utf8Error> {
+        std::str::from_utf8(self.name.as_ref())
+    }
+
+    /// Number of arguments.
+    pub fn arg_count(&self) -> usize {
+        self.arguments.len()
+    }
+
+    /// Get argument `index` as bytes, or [None] if there are not that many
+    /// arguments.
+    pub fn arg(&self, index: usize) -> Option<&[u8]> {
+        self.arguments.get(index).map(|arg| arg.as_ref())
+    }
+
+    /// Convert to a message that owns all its data.
+    ///
+    /// Fields that already own a [Vec] (such as [Cow::Owned]) are moved
+    /// rather than copied.
+    pub fn into_owned(self) -> OwnedMessage
+    where
+        N: Into<Vec<u8>>,
+        A: Into<Vec<u8>>,
+    {
+        Message::new(
+            self.mtype,
+            self.name.into(),
+            self.mid,
+            self.arguments
+                .into_iter()
+                .map(Into::into)
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    /// Take ownership of the arguments.
+    ///
+    /// As for [into_owned](Message::into_owned), arguments that already own
+    /// a [Vec] are moved rather than copied.
+    pub fn into_arguments(self) -> impl Iterator<Item = Vec<u8>>
+    where
+        A: Into<Vec<u8>>,
+    {
+        self.arguments.into_iter().map(Into::into)
+    }
+
+    /// Get a [CowMessage] that borrows from this message.
+    pub fn as_cow(&self) -> CowMessage<'_> {
+        Message::new(
+            self.mtype,
+            Cow::Borrowed(self.name.as_ref()),
+            self.mid,
+            self.arguments
+                .iter()
+                .map(|arg| Cow::Borrowed(arg.as_ref()))
+                .collect::<Vec<_>>(),
+        )
+    }
+}
+
+impl<A> Message<&'static [u8], A>
+where
+    A: AsRef<[u8]>,
+{
+    /// Create a new message whose name is a static byte string.
+    ///
+    /// This is equivalent to [Message::new], but fixes the name type so
+    /// that callers do not need to specify it.
+    pub fn with_static_name(
+        mtype: MessageType,
+        name: &'static [u8],
+        mid: Option<u32>,
+        arguments: impl Into<Vec<A>>,
+    ) -> Self {
+        Self::new(mtype, name, mid, arguments)
+    }
+}
+
+/// Incremental construction of an [OwnedMessage] from typed arguments.
+///
+/// Each argument is encoded as it is added, using the encodings in
+/// [crate::types].
+#[derive(Clone, Debug)]
+pub struct MessageBuilder {
+    message: OwnedMessage,
+}
+
+impl MessageBuilder {
+    /// Start a message with no message ID or arguments.
+    pub fn new(mtype: MessageType, name: impl Into<Vec<u8>>) -> Self {
+        Self {
+            message: Message::new(mtype, name, None, vec![]),
+        }
+    }
+
+    /// Set the message type.
+    pub fn mtype(mut self, mtype: MessageType) -> Self {
+        self.message.mtype = mtype;
+        self
+    }
+
+    /// Set the name.
+    pub fn name(mut self, name: impl Into<Vec<u8>>) -> Self {
+        self.message.name = name.into();
+        self
+    }
+
+    /// Set the message ID.
+    pub fn mid(mut self, mid: impl Into<Option<u32>>) -> Self {
+        self.message.mid = mid.into();
+        self
+    }
+
+    /// Append an argument without any conversion.
+    pub fn arg_bytes(mut self, value: impl Into<Vec<u8>>) -> Self {
+        self.message.arguments.push(value.into());
+        self
+    }
+
+    /// Append a string argument.
+    pub fn arg_str(self, value: &str) -> Self {
+        self.arg_bytes(value)
+    }
+
+    /// Append an integer argument.
+    pub fn arg_int(self, value: i64) -> Self {
+        self.arg_bytes(value.to_string())
+    }
+
+    /// Append a floating-point argument (see [crate::types::format_float]).
+    pub fn arg_float(self, value: f64) -> Self {
+        self.arg_bytes(crate::types::format_float(value))
+    }
+
+    /// Append a boolean argument (see [crate::types::format_bool]).
+    pub fn arg_bool(self, value: bool) -> Self {
+        self.arg_bytes(crate::types::format_bool(value))
+    }
+
+    /// Finish the message.
+    ///
+    /// The name and message ID are not checked; use [Message::validate] to
+    /// check them.
+    pub fn build(self) -> OwnedMessage {
+        self.message
+    }
 }
 
 /// Message type used for interaction with Python.
-#[pyclass(name = "Message", module = "katcp_codec._lib", get_all, set_all)]
+///
+/// The name and arguments are stored as `bytes`, but may be given as `str`,
+/// in which case they are encoded as UTF-8.
+#[pyclass(name = "Message", module = "katcp_codec._lib")]
 pub struct PyMessage {
+    #[pyo3(get, set)]
     pub mtype: MessageType,
+    #[pyo3(get)]
     pub name: Option<Py<PyBytes>>, // Option only to support __clear__
+    #[pyo3(get, set)]
     pub mid: Option<u32>,
+    #[pyo3(get)]
     pub arguments: Option<Py<PyList>>, // Option only to support __clear__
 }
 
+/// Convert a `str` or `bytes` to `bytes`.
+fn py_to_bytes<'py>(value: &Bound<'py, PyAny>, what: &str) -> PyResult<Bound<'py, PyBytes>> {
+    if let Ok(bytes) = value.downcast::<PyBytes>() {
+        Ok(bytes.clone())
+    } else if let Ok(string) = value.downcast::<PyString>() {
+        Ok(PyBytes::new_bound(value.py(), string.to_cow()?.as_bytes()))
+    } else {
+        Err(PyTypeError::new_err(format!("{what} must be str or bytes")))
+    }
+}
+
+/// Convert a list of `str` or `bytes` to a list of `bytes`.
+///
+/// If the elements are all `bytes` already, the list itself is returned.
+fn py_to_bytes_list<'py>(value: &Bound<'py, PyList>) -> PyResult<Bound<'py, PyList>> {
+    if value.iter().all(|arg| arg.is_instance_of::<PyBytes>()) {
+        return Ok(value.clone());
+    }
+    let arguments = value
+        .iter()
+        .map(|arg| py_to_bytes(&arg, "argument"))
+        .collect::<PyResult<Vec<_>>>()?;
+    Ok(PyList::new_bound(value.py(), arguments))
+}
+
 impl PyMessage {
     /// Construct a new message.
     pub fn new(
@@ -113,6 +508,47 @@ impl PyMessage {
             arguments: Some(arguments),
         }
     }
+
+    /// Construct a Python message from a Rust [Message].
+    ///
+    /// The name and arguments are copied into new Python objects.
+    pub fn from_message<N, A>(py: Python<'_>, message: &Message<N, A>) -> Self
+    where
+        N: AsRef<[u8]>,
+        A: AsRef<[u8]>,
+    {
+        Self::new(
+            message.mtype,
+            PyBytes::new_bound(py, message.name.as_ref()).unbind(),
+            message.mid,
+            PyList::new_bound(py, message.arguments.iter().map(|x| Cow::from(x.as_ref()))).unbind(),
+        )
+    }
+
+    fn py_arguments<'py>(&self, py: Python<'py>) -> PyResult<&Bound<'py, PyList>> {
+        self.arguments
+            .as_ref()
+            .map(|arguments| arguments.bind(py))
+            .ok_or_else(|| PyValueError::new_err("arguments is None"))
+    }
+
+    /// Borrow the contents as a Rust [Message], for formatting.
+    fn to_message(&self, py: Python<'_>) -> PyResult<Message<&[u8], PyBackedBytes>> {
+        let name = self
+            .name
+            .as_ref()
+            .ok_or_else(|| PyValueError::new_err("name is None"))?;
+        let arguments = self.py_arguments(py)?;
+        // TODO: this is creating a new vector to hold the arguments.
+        // Can we use another trait to handle directly iterating the PyList?
+        let arguments: Vec<PyBackedBytes> = arguments.extract()?;
+        Ok(Message {
+            mtype: self.mtype,
+            name: name.as_bytes(py),
+            mid: self.mid,
+            arguments,
+        })
+    }
 }
 
 #[pymethods]
@@ -121,11 +557,28 @@ impl PyMessage {
     #[pyo3(signature = (mtype, name, mid, arguments))]
     fn py_new<'py>(
         mtype: MessageType,
-        name: Bound<'py, PyBytes>,
+        name: &Bound<'py, PyAny>,
         mid: Option<u32>,
-        arguments: Bound<'py, PyList>,
-    ) -> Self {
-        Self::new(mtype, name.unbind(), mid, arguments.unbind())
+        arguments: &Bound<'py, PyList>,
+    ) -> PyResult<Self> {
+        Ok(Self::new(
+            mtype,
+            py_to_bytes(name, "name")?.unbind(),
+            mid,
+            py_to_bytes_list(arguments)?.unbind(),
+        ))
+    }
+
+    #[setter]
+    fn set_name(&mut self, name: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.name = Some(py_to_bytes(name, "name")?.unbind());
+        Ok(())
+    }
+
+    #[setter]
+    fn set_arguments(&mut self, arguments: &Bound<'_, PyList>) -> PyResult<()> {
+        self.arguments = Some(py_to_bytes_list(arguments)?.unbind());
+        Ok(())
     }
 
     // See https://pyo3.rs/v0.21.2/class/protocols#garbage-collector-integration
@@ -144,25 +597,114 @@ impl PyMessage {
         self.arguments = None;
     }
 
-    fn __bytes__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
-        let name = self
-            .name
-            .as_ref()
-            .ok_or_else(|| PyValueError::new_err("name is None"))?;
-        let name = name.bind(py);
-        let arguments = self
-            .arguments
-            .as_ref()
-            .ok_or_else(|| PyValueError::new_err("arguments is None"))?;
-        // TODO: this is creating a new vector to hold the arguments.
-        // Can we use another trait to handle directly iterating the PyList?
-        let arguments: Vec<PyBackedBytes> = arguments.bind(py).extract()?;
-        let message = Message {
-            mtype: self.mtype,
-            name: name.as_bytes(),
-            mid: self.mid,
-            arguments,
+    /// Number of arguments
+    #[getter]
+    fn argument_count(&self, py: Python<'_>) -> PyResult<usize> {
+        Ok(self.py_arguments(py)?.len())
+    }
+
+    /// Number of bytes in the wire representation, computed without
+    /// encoding the message.
+    #[getter]
+    fn encoded_size(&self, py: Python<'_>) -> PyResult<usize> {
+        Ok(self.to_message(py)?.write_size())
+    }
+
+    /// Get a single argument without converting the whole list.
+    ///
+    /// As for a Python sequence, a negative index counts from the end.
+    fn argument_at<'py>(&self, py: Python<'py>, index: isize) -> PyResult<Bound<'py, PyAny>> {
+        let arguments = self.py_arguments(py)?;
+        let len = arguments.len() as isize;
+        let index = if index < 0 { index + len } else { index };
+        if !(0..len).contains(&index) {
+            return Err(PyIndexError::new_err("argument index out of range"));
+        }
+        arguments.get_item(index as usize)
+    }
+
+    /// Support pickling, by passing the fields to the constructor.
+    #[allow(clippy::type_complexity)]
+    fn __reduce__(
+        slf: &Bound<'_, Self>,
+    ) -> (
+        PyObject,
+        (
+            MessageType,
+            Option<Py<PyBytes>>,
+            Option<u32>,
+            Option<Py<PyList>>,
+        ),
+    ) {
+        let py = slf.py();
+        let this = slf.borrow();
+        let fields = (
+            this.mtype,
+            this.name.as_ref().map(|name| name.clone_ref(py)),
+            this.mid,
+            this.arguments
+                .as_ref()
+                .map(|arguments| arguments.clone_ref(py)),
+        );
+        (py.get_type_bound::<Self>().into_any().unbind(), fields)
+    }
+
+    /// Messages are equal if all their fields are equal. The fields are
+    /// mutable, so messages are not hashable.
+    fn __eq__(&self, py: Python<'_>, other: &Bound<'_, PyAny>) -> PyResult<PyObject> {
+        let Ok(other) = other.downcast::<PyMessage>() else {
+            return Ok(py.NotImplemented());
+        };
+        let other = other.borrow();
+        let name_eq = match (&self.name, &other.name) {
+            (Some(a), Some(b)) => a.as_bytes(py) == b.as_bytes(py),
+            (a, b) => a.is_none() && b.is_none(),
         };
+        let arguments_eq = match (&self.arguments, &other.arguments) {
+            (Some(a), Some(b)) => a.bind(py).eq(b.bind(py))?,
+            (a, b) => a.is_none() && b.is_none(),
+        };
+        let eq = self.mtype == other.mtype && self.mid == other.mid && name_eq && arguments_eq;
+        Ok(eq.into_py(py))
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> PyResult<String> {
+        let mtype = self.mtype.py_name();
+        let name = match &self.name {
+            Some(name) => name.bind(py).repr()?.to_string(),
+            None => "None".to_owned(),
+        };
+        let mid = match self.mid {
+            Some(mid) => mid.to_string(),
+            None => "None".to_owned(),
+        };
+        let arguments = match &self.arguments {
+            Some(arguments) => arguments.bind(py).repr()?.to_string(),
+            None => "None".to_owned(),
+        };
+        Ok(format!(
+            "Message({mtype}, {name}, mid={mid}, arguments={arguments})"
+        ))
+    }
+
+    /// Encode the message into the start of a bytearray.
+    ///
+    /// Returns the number of bytes written. Only :class:`bytearray` is
+    /// supported, because the general buffer protocol is not part of the
+    /// limited Python API that this module is built against.
+    fn write_into(&self, py: Python<'_>, buffer: &Bound<'_, PyByteArray>) -> PyResult<usize> {
+        let message = self.to_message(py)?;
+        // SAFETY: the GIL is held and no Python code runs while the slice
+        // exists, so the bytearray cannot be resized or accessed
+        // concurrently.
+        let bytes = unsafe { buffer.as_bytes_mut() };
+        message
+            .write_slice(bytes)
+            .map_err(|err| PyValueError::new_err(err.to_string()))
+    }
+
+    fn __bytes__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let message = self.to_message(py)?;
         let size = message.write_size();
         PyBytes::new_bound_with(py, size, |bytes: &mut [u8]| {
             let remain = message.write_out(bytes.as_out());
@@ -184,12 +726,6 @@ where
     A: AsRef<[u8]>,
 {
     fn to_object(&self, py: Python<'_>) -> PyObject {
-        let py_msg = PyMessage::new(
-            self.mtype,
-            PyBytes::new_bound(py, self.name.as_ref()).unbind(),
-            self.mid,
-            PyList::new_bound(py, self.arguments.iter().map(|x| Cow::from(x.as_ref()))).unbind(),
-        );
-        py_msg.into_py(py)
+        PyMessage::from_message(py, self).into_py(py)
     }
 }
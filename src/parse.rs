@@ -13,37 +13,289 @@
  * limitations under the License.
  */
 
-use pyo3::exceptions::PyValueError;
+use pyo3::exceptions::PyRuntimeError;
 use pyo3::prelude::*;
 use pyo3::types::{PyBytes, PyList};
-use std::borrow::Cow;
+use std::borrow::{Borrow, Cow};
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hash};
+use std::io::{self, IoSlice, Read};
+use std::sync::Arc;
+use std::time::Instant;
 use thiserror::Error;
 
+pub use katcp_codec_fsm::KatcpVersion;
 use katcp_codec_fsm::{Action, State};
 
-use crate::message::{Message, MessageType};
-use crate::tables::PARSER_TABLE;
+use crate::binding::ParseError as PyParseError;
+use crate::message::{
+    CowMessage, Message, MessageType, MessageTypeSet, OwnedMessage, MAX_MESSAGE_ID,
+};
+use crate::scan::find_rejected;
+use crate::tables::{lookup, ParserTable, PARSER_TABLE, PARSER_TABLE_V4};
 
-type ParsedMessage<'data> = Message<Cow<'data, [u8]>, Cow<'data, [u8]>>;
+type ParsedMessage<'data> = CowMessage<'data>;
+
+/// Reason for a [ParseError].
+#[derive(Error, Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ParseErrorKind {
+    /// A character that is not valid at this point in the message
+    #[error("Invalid character")]
+    InvalidCharacter,
+    /// The line exceeds the maximum line length
+    #[error("Line too long")]
+    LineTooLong,
+    /// The message ID is too large
+    #[error("Message ID overflowed")]
+    MessageIdOverflow,
+    /// The brackets for a message ID are empty
+    ///
+    /// This displays as "Invalid character" for compatibility with earlier
+    /// versions, which did not distinguish it.
+    #[error("Invalid character")]
+    EmptyMessageId,
+    /// A backslash is followed by a character that is not a valid escape
+    ///
+    /// This displays as "Invalid character" for compatibility with earlier
+    /// versions, which did not distinguish it.
+    #[error("Invalid character")]
+    BadEscape,
+    /// An inform has a message ID (see [ParserOptions::forbid_inform_id])
+    #[error("Message ID not allowed on inform")]
+    InformIdNotAllowed,
+    /// See [ParserOptions::max_escapes_per_argument]
+    #[error("Too many escapes in argument")]
+    TooManyEscapes,
+    /// See [ParserOptions::max_arguments]
+    #[error("Too many arguments")]
+    TooManyArguments,
+    /// See [ParserOptions::max_name_length]
+    #[error("Name too long")]
+    NameTooLong,
+    /// Data follows the message (see [Message::from_bytes])
+    #[error("Trailing data")]
+    TrailingData,
+    /// The input ended after a backslash (see [Parser::finish])
+    #[error("Unterminated escape")]
+    UnterminatedEscape,
+    /// The input ended where the line could not be terminated, such as
+    /// inside a message ID (see [Parser::finish])
+    #[error("Unexpected end of input")]
+    UnexpectedEnd,
+}
 
 /// Error returned from parsing.
 #[derive(Error, Clone, Debug, Eq, PartialEq)]
-#[error("{message:?} at character {position:?}")]
+#[error("{:?} at character {position:?}", .kind.to_string())]
 pub struct ParseError {
-    message: String,
+    kind: ParseErrorKind,
     position: usize,
+    location: FieldLocation,
+    partial: Option<Box<PartialMessage>>,
+    /// Offset of the start of the line within the stream
+    line_offset: u64,
 }
 
 impl ParseError {
     /// Create a new error.
-    fn new(message: impl Into<String>, position: usize) -> Self {
+    fn new(kind: ParseErrorKind, position: usize, location: FieldLocation) -> Self {
         Self {
-            message: message.into(),
+            kind,
             position,
+            location,
+            partial: None,
+            line_offset: 0,
+        }
+    }
+
+    /// The reason for the error.
+    pub fn kind(&self) -> ParseErrorKind {
+        self.kind
+    }
+
+    /// The part of the message that was being parsed when the error occurred.
+    pub fn field_location(&self) -> FieldLocation {
+        self.location
+    }
+
+    /// The parts of the message that were parsed before the error.
+    ///
+    /// This is only available if [ParserOptions::keep_partial] is set and
+    /// the message type was parsed.
+    pub fn partial(&self) -> Option<&PartialMessage> {
+        self.partial.as_deref()
+    }
+
+    /// The 0-based offset of the offending character from the start of all
+    /// the data passed to the [Parser] (see [Parser::stream_offset]).
+    ///
+    /// For errors not produced by a [Parser], the offset is relative to the
+    /// start of the input.
+    pub fn stream_position(&self) -> u64 {
+        self.line_offset + self.position.saturating_sub(1) as u64
+    }
+}
+
+impl ParseError {
+    /// Convert to a Python exception, with the position and message as
+    /// attributes.
+    fn to_py(&self, py: Python<'_>) -> PyResult<PyObject> {
+        let value = PyParseError::new_err(self.to_string()).into_value(py);
+        let bound = value.bind(py);
+        bound.setattr("position", self.position)?;
+        bound.setattr("stream_position", self.stream_position())?;
+        bound.setattr("message", self.kind.to_string())?;
+        Ok(value.into_any())
+    }
+}
+
+/// The parts of a message that were successfully parsed before a
+/// [ParseError]. Fields which had not been completed are omitted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PartialMessage {
+    /// Message type
+    pub mtype: MessageType,
+    /// Message name, if it was complete
+    pub name: Option<Vec<u8>>,
+    /// Message ID, if there was one and it was complete
+    pub mid: Option<u32>,
+    /// Arguments that were complete
+    pub arguments: Vec<Vec<u8>>,
+}
+
+/// Part of a message, used to locate a [ParseError].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum FieldLocation {
+    /// The message type, or leading whitespace
+    Type,
+    /// The message name
+    Name,
+    /// The message ID, including its delimiters
+    Id,
+    /// An argument (given by 0-based index), or the whitespace preceding it
+    Argument(usize),
+}
+
+impl FieldLocation {
+    /// Determine the location from the state in which an error occurred.
+    fn from_state(state: State, num_arguments: usize) -> Self {
+        match state {
+            State::BeforeName | State::Name => FieldLocation::Name,
+            State::BeforeId
+            | State::Id
+            | State::AfterId
+            | State::BeforeColonId
+            | State::ColonId => FieldLocation::Id,
+            State::BeforeArgument => FieldLocation::Argument(num_arguments),
+            State::Argument | State::ArgumentEscape => {
+                FieldLocation::Argument(num_arguments.saturating_sub(1))
+            }
+            // Errors cannot occur in the other states, or (for the error
+            // states) are already recorded.
+            _ => FieldLocation::Type,
         }
     }
 }
 
+/// Summary of what a [Parser] is waiting for, returned by
+/// [Parser::current_state].
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ParserPhase {
+    /// At the start of a line
+    Idle,
+    /// In a line that so far contains only whitespace
+    BlankLine,
+    /// After the message type, in or before the name
+    Name,
+    /// In the message ID
+    Id,
+    /// In the whitespace before an argument
+    BeforeArgument,
+    /// In an argument
+    Argument,
+    /// In an argument, immediately after a backslash
+    Escape,
+    /// In a line skipped by [Parser::append_filtered]
+    Skip,
+    /// In a line with an error, which will be reported at the end of the
+    /// line
+    Error,
+}
+
+/// Saved parser state, created by [Parser::checkpoint].
+///
+/// This includes any message in progress, so it can be taken partway
+/// through a line.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Checkpoint {
+    state: State,
+    line_length: usize,
+    mtype: Option<MessageType>,
+    name: Vec<u8>,
+    mid: Option<u32>,
+    mid_overflowed: bool,
+    after_cr: bool,
+    arguments: Vec<Vec<u8>>,
+    error: Option<ParseError>,
+    raw_line: Vec<u8>,
+    escapes: usize,
+    had_tab_separator: bool,
+    had_multiple_separators: bool,
+    messages_parsed: u64,
+    lines_seen: u64,
+    stream_offset: u64,
+    line_offset: u64,
+}
+
+impl Checkpoint {
+    /// Whether the checkpoint was taken at a message boundary.
+    pub fn at_boundary(&self) -> bool {
+        self.state == State::Start
+    }
+}
+
+/// Maps message names to small integer symbols.
+///
+/// See [ParserOptions::name_interner].
+pub trait NameInterner: std::fmt::Debug + Send + Sync {
+    /// Look up the symbol for a name, if it is known.
+    ///
+    /// This is called once per message, so it should not allocate.
+    fn lookup(&self, name: &[u8]) -> Option<u32>;
+}
+
+impl<K, S> NameInterner for HashMap<K, u32, S>
+where
+    K: Borrow<[u8]> + Hash + Eq + std::fmt::Debug + Send + Sync,
+    S: BuildHasher + Send + Sync,
+{
+    fn lookup(&self, name: &[u8]) -> Option<u32> {
+        self.get(name).copied()
+    }
+}
+
+/// Additional information about a parsed message.
+///
+/// This is obtained from [ParseIterator::with_info].
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct MessageInfo {
+    /// Symbol for the name from [ParserOptions::name_interner], if any
+    pub name_symbol: Option<u32>,
+    /// Number of bytes in the line on the wire, including the terminator.
+    /// This is also the smallest maximum line length that would accept
+    /// the message.
+    pub wire_length: usize,
+    /// Time at which the end of the line was parsed, from
+    /// [ParserOptions::on_message_time]
+    pub completed_at: Option<Instant>,
+    /// Whether any of the separators between fields was a tab
+    pub had_tab_separator: bool,
+    /// Whether any of the fields was followed by more than one whitespace
+    /// character. A single space or tab at the end of the line is not
+    /// counted.
+    pub had_multiple_separators: bool,
+}
+
 /// Iterator implementation for [Parser::append].
 pub struct ParseIterator<'parser, 'data>
 where
@@ -52,6 +304,8 @@ where
     parser: &'parser mut Parser,
     data: &'data [u8],
     transient: Transient<'data>,
+    /// Message most recently returned by [ParseIterator::next_ref]
+    current: Option<ParsedMessage<'data>>,
 }
 
 impl<'parser, 'data> Iterator for ParseIterator<'parser, 'data>
@@ -67,12 +321,478 @@ where
     }
 }
 
+impl<'parser, 'data> ParseIterator<'parser, 'data>
+where
+    'data: 'parser,
+{
+    /// Yield the raw bytes of each line alongside the parsed message or
+    /// the error.
+    ///
+    /// The raw bytes are exactly those received for the line, including the
+    /// terminator, and are borrowed from the input where possible. For a
+    /// line that fails to parse, at most the maximum line length is kept, so
+    /// a line that is too long is truncated. With [ParserOptions::merge_crlf],
+    /// the `\n` of a `\r\n` terminator is not included, since the line is
+    /// reported before the `\n` is seen.
+    ///
+    /// # Panics
+    ///
+    /// If the parser was not created with
+    /// [ParserOptions::capture_raw_lines]. The option is needed because a
+    /// line may have started in an earlier chunk.
+    pub fn with_raw_lines(self) -> RawLineIterator<'parser, 'data> {
+        assert!(
+            self.parser.options.capture_raw_lines,
+            "with_raw_lines requires ParserOptions::capture_raw_lines"
+        );
+        RawLineIterator { inner: self }
+    }
+
+    /// Yield a [MessageInfo] alongside each parsed message.
+    pub fn with_info(self) -> InfoIterator<'parser, 'data> {
+        InfoIterator { inner: self }
+    }
+
+    /// Also yield [ParseEvent::BlankLine] for each blank line.
+    pub fn with_blank_lines(mut self) -> BlankLineIterator<'parser, 'data> {
+        self.transient.report_blank_lines = true;
+        BlankLineIterator { inner: self }
+    }
+
+    /// Return the next message by reference, reusing storage from the
+    /// previous one.
+    ///
+    /// This is an alternative to [Iterator::next] for callers that finish
+    /// with each message before requesting the next. The message is stored
+    /// in the iterator, and the next call to this method replaces it (the
+    /// borrow checker prevents the reference from being used after that).
+    /// The argument vector is recycled, so a message that lies entirely
+    /// within the data passed to [Parser::append] needs no allocations.
+    pub fn next_ref(&mut self) -> Option<Result<&ParsedMessage<'data>, ParseError>> {
+        if let Some(previous) = self.current.take() {
+            let mut arguments = previous.arguments;
+            arguments.clear();
+            if self.transient.arguments.is_empty() {
+                self.transient.arguments = arguments;
+            }
+        }
+        match self.next()? {
+            Ok(msg) => Some(Ok(self.current.insert(msg))),
+            Err(error) => Some(Err(error)),
+        }
+    }
+
+    /// Merge runs of identical messages (see [Coalescer]).
+    pub fn coalesce(self) -> Coalescer<Self, ParsedMessage<'data>> {
+        Coalescer::new(self)
+    }
+}
+
+/// Iterator implementation for [ParseIterator::with_info].
+pub struct InfoIterator<'parser, 'data>
+where
+    'data: 'parser,
+{
+    inner: ParseIterator<'parser, 'data>,
+}
+
+impl<'parser, 'data> Iterator for InfoIterator<'parser, 'data>
+where
+    'data: 'parser,
+{
+    type Item = Result<(ParsedMessage<'data>, MessageInfo), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let msg = self.inner.next()?;
+        let info = std::mem::take(&mut self.inner.transient.info);
+        Some(msg.map(|msg| (msg, info)))
+    }
+}
+
+/// Item produced by [ParseIterator::with_blank_lines].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseEvent<'data> {
+    /// A message, as would be produced by [ParseIterator]
+    Message(CowMessage<'data>),
+    /// A line that is empty or contains only spaces.
+    ///
+    /// Both `\r` and `\n` terminate a line, so the `\n` of a `\r\n` line
+    /// ending is reported as a blank line. Lines skipped by
+    /// [Parser::append_filtered] are not reported.
+    BlankLine,
+}
+
+/// Iterator implementation for [ParseIterator::with_blank_lines].
+pub struct BlankLineIterator<'parser, 'data>
+where
+    'data: 'parser,
+{
+    inner: ParseIterator<'parser, 'data>,
+}
+
+impl<'parser, 'data> Iterator for BlankLineIterator<'parser, 'data>
+where
+    'data: 'parser,
+{
+    type Item = Result<ParseEvent<'data>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.inner.next() {
+            Some(msg) => Some(msg.map(ParseEvent::Message)),
+            None if std::mem::take(&mut self.inner.transient.blank_line) => {
+                Some(Ok(ParseEvent::BlankLine))
+            }
+            None => None,
+        }
+    }
+}
+
+/// Iterator implementation for [ParseIterator::with_raw_lines].
+pub struct RawLineIterator<'parser, 'data>
+where
+    'data: 'parser,
+{
+    inner: ParseIterator<'parser, 'data>,
+}
+
+impl<'parser, 'data> Iterator for RawLineIterator<'parser, 'data>
+where
+    'data: 'parser,
+{
+    type Item = Result<(ParsedMessage<'data>, Cow<'data, [u8]>), (ParseError, Cow<'data, [u8]>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let msg = self.inner.next()?;
+        let raw = std::mem::take(&mut self.inner.transient.raw_line);
+        Some(match msg {
+            Ok(msg) => Ok((msg, raw)),
+            Err(error) => Err((error, raw)),
+        })
+    }
+}
+
+/// Field of a message produced by [Parser::append_shared].
+#[derive(Clone, Debug, Eq, PartialEq, Hash)]
+pub enum SharedField<B> {
+    /// A sub-slice of the buffer passed to [Parser::append_shared]
+    Shared(B),
+    /// A copy, for a field that contained escape sequences or that was
+    /// started by an earlier call to the parser
+    Owned(Vec<u8>),
+}
+
+impl<B: AsRef<[u8]>> AsRef<[u8]> for SharedField<B> {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            SharedField::Shared(slice) => slice.as_ref(),
+            SharedField::Owned(vec) => vec,
+        }
+    }
+}
+
+impl<B: AsRef<[u8]>> From<SharedField<B>> for Vec<u8> {
+    fn from(field: SharedField<B>) -> Self {
+        match field {
+            SharedField::Shared(slice) => slice.as_ref().to_vec(),
+            SharedField::Owned(vec) => vec,
+        }
+    }
+}
+
+/// A [Message] produced by [Parser::append_shared].
+pub type SharedMessage<B> = Message<SharedField<B>, SharedField<B>>;
+
+/// Iterator implementation for [Parser::append_shared].
+pub struct SharedParseIterator<'parser, 'data, F>
+where
+    'data: 'parser,
+{
+    inner: ParseIterator<'parser, 'data>,
+    /// All the data passed to [Parser::append_shared]
+    data: &'data [u8],
+    share: F,
+}
+
+impl<'parser, 'data, B, F> SharedParseIterator<'parser, 'data, F>
+where
+    'data: 'parser,
+    B: AsRef<[u8]>,
+    F: FnMut(&'data [u8]) -> B,
+{
+    fn field(&mut self, field: Cow<'data, [u8]>) -> SharedField<B> {
+        match field {
+            Cow::Borrowed(slice) => {
+                let range = self.data.as_ptr_range();
+                if range.contains(&slice.as_ptr()) && slice.as_ptr_range().end <= range.end {
+                    SharedField::Shared((self.share)(slice))
+                } else {
+                    // Only an empty field can be borrowed from elsewhere
+                    SharedField::Owned(slice.to_vec())
+                }
+            }
+            Cow::Owned(vec) => SharedField::Owned(vec),
+        }
+    }
+}
+
+impl<'parser, 'data, B, F> Iterator for SharedParseIterator<'parser, 'data, F>
+where
+    'data: 'parser,
+    B: AsRef<[u8]>,
+    F: FnMut(&'data [u8]) -> B,
+{
+    type Item = Result<SharedMessage<B>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let msg = match self.inner.next()? {
+            Ok(msg) => msg,
+            Err(error) => return Some(Err(error)),
+        };
+        let name = self.field(msg.name);
+        let arguments: Vec<_> = msg
+            .arguments
+            .into_iter()
+            .map(|arg| self.field(arg))
+            .collect();
+        Some(Ok(Message::new(msg.mtype, name, msg.mid, arguments)))
+    }
+}
+
+/// Iterator implementation for [Parser::append_vectored].
+pub struct VectoredParseIterator<'parser, 'data>
+where
+    'data: 'parser,
+{
+    /// Iterator over the current slice
+    inner: ParseIterator<'parser, 'data>,
+    /// Slices not yet passed to `inner`
+    slices: &'data [IoSlice<'data>],
+}
+
+impl<'parser, 'data> Iterator for VectoredParseIterator<'parser, 'data>
+where
+    'data: 'parser,
+{
+    type Item = Result<ParsedMessage<'data>, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(msg) = self.inner.next() {
+                return Some(msg);
+            }
+            // The current slice is exhausted, so its state has been
+            // returned to the parser, and we can move on to the next one.
+            let (first, rest) = self.slices.split_first()?;
+            self.slices = rest;
+            self.inner.data = first;
+            self.inner.transient = self.inner.parser.new_transient(first);
+        }
+    }
+}
+
+/// Iterator adaptor that merges runs of identical messages.
+///
+/// Each item is a message together with the number of consecutive times it
+/// was received. Errors are passed through, and also end any run in
+/// progress. Runs are only detected within a single underlying iterator,
+/// so a run split across two calls to [Parser::append] is reported as two
+/// separate messages.
+pub struct Coalescer<I, M>
+where
+    I: Iterator<Item = Result<M, ParseError>>,
+    M: PartialEq,
+{
+    inner: I,
+    /// Message in the current run, and the length of the run
+    pending: Option<(M, usize)>,
+    /// Error that ended the previous run, to be yielded next
+    error: Option<ParseError>,
+}
+
+impl<I, M> Coalescer<I, M>
+where
+    I: Iterator<Item = Result<M, ParseError>>,
+    M: PartialEq,
+{
+    /// Wrap an iterator of parsed messages.
+    pub fn new(inner: I) -> Self {
+        Self {
+            inner,
+            pending: None,
+            error: None,
+        }
+    }
+}
+
+impl<I, M> Iterator for Coalescer<I, M>
+where
+    I: Iterator<Item = Result<M, ParseError>>,
+    M: PartialEq,
+{
+    type Item = Result<(M, usize), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.error.take() {
+            return Some(Err(err));
+        }
+        loop {
+            match self.inner.next() {
+                Some(Ok(msg)) => match &mut self.pending {
+                    Some((prev, count)) if *prev == msg => *count += 1,
+                    _ => {
+                        if let Some(prev) = self.pending.replace((msg, 1)) {
+                            return Some(Ok(prev));
+                        }
+                    }
+                },
+                Some(Err(err)) => match self.pending.take() {
+                    Some(prev) => {
+                        self.error = Some(err);
+                        return Some(Ok(prev));
+                    }
+                    None => return Some(Err(err)),
+                },
+                None => return self.pending.take().map(Ok),
+            }
+        }
+    }
+}
+
 /// Parser state that can only live as long as the iterator returned by [Parser::append].
 struct Transient<'data> {
     /// Name which *replaces* [Parser::name]
     name: Cow<'data, [u8]>,
     /// Arguments to *append* to [Parser::arguments]
     arguments: Vec<Cow<'data, [u8]>>,
+    /// Data from the start of the current line in this chunk (only when
+    /// capturing raw lines)
+    line_start: Option<&'data [u8]>,
+    /// Raw bytes of the most recently completed line (only when capturing
+    /// raw lines)
+    raw_line: Cow<'data, [u8]>,
+    /// Information about the most recently completed message
+    info: MessageInfo,
+    /// Whether to stop at blank lines (see [ParseIterator::with_blank_lines])
+    report_blank_lines: bool,
+    /// Set when [Parser::next_message] stops at a blank line
+    blank_line: bool,
+}
+
+/// What to do with a message ID that is larger than [MAX_MESSAGE_ID] (see
+/// [ParserOptions::on_mid_overflow]).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum OnMidOverflow {
+    /// Report [ParseErrorKind::MessageIdOverflow]
+    #[default]
+    Error,
+    /// Replace the ID with [MAX_MESSAGE_ID]
+    Clamp,
+    /// Parse the message as if it had no ID
+    Drop,
+}
+
+/// How the parser consumed its input (see [Parser::stats]).
+///
+/// Every state transition consumes one byte through the state table, and
+/// may then consume more in bulk.
+#[cfg(feature = "stats")]
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub struct ParserStats {
+    /// Number of state transitions, and hence bytes consumed one at a time
+    pub transitions: u64,
+    /// Number of transitions that consumed extra bytes using the fast table
+    pub fast_path_runs: u64,
+    /// Extra bytes consumed using the fast table
+    pub fast_path_bytes: u64,
+    /// Extra bytes consumed by merging runs of a repeated escape sequence
+    pub escape_run_bytes: u64,
+}
+
+/// Which whitespace bytes separate the fields of a message (see
+/// [ParserOptions::separators]).
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq, Hash)]
+pub enum Separators {
+    /// Both space and tab, as in the katcp specification
+    #[default]
+    SpaceOrTab,
+    /// Only space; a tab is part of an argument
+    Space,
+    /// Only tab; a space is part of an argument
+    Tab,
+}
+
+impl Separators {
+    /// The whitespace byte that is not a separator, if any.
+    fn disabled(self) -> Option<u8> {
+        match self {
+            Separators::SpaceOrTab => None,
+            Separators::Space => Some(b'\t'),
+            Separators::Tab => Some(b' '),
+        }
+    }
+}
+
+/// Optional parser behaviour.
+///
+/// The defaults give a parser that accepts exactly the katcp grammar.
+#[derive(Clone, Debug, Default)]
+pub struct ParserOptions {
+    /// Also accept message IDs written as `?name:123`, in addition to
+    /// `?name[123]`. The ID is terminated by whitespace or the end of the
+    /// line.
+    pub allow_colon_id: bool,
+    /// Reject informs that have a message ID.
+    pub forbid_inform_id: bool,
+    /// Keep a copy of each line as received, for use with
+    /// [ParseIterator::with_raw_lines].
+    pub capture_raw_lines: bool,
+    /// Names to look up when each message is complete. The result is
+    /// reported in [MessageInfo::name_symbol].
+    pub name_interner: Option<Arc<dyn NameInterner>>,
+    /// Clock to read when each message is complete. The result is
+    /// reported in [MessageInfo::completed_at]. It is called once per
+    /// message, not once per byte.
+    pub on_message_time: Option<fn() -> Instant>,
+    /// Maximum number of escape sequences (other than `\@`) in a single
+    /// argument. Arguments that are mostly escape sequences are slower to
+    /// parse, and may be much longer on the wire than once decoded.
+    pub max_escapes_per_argument: Option<usize>,
+    /// Maximum number of arguments in a message. Empty arguments take
+    /// little space on the wire, so without a limit a single line can
+    /// produce a very large number of them.
+    pub max_arguments: Option<usize>,
+    /// Maximum number of bytes in a message name. Real names are short, so
+    /// a very long name usually means that the stream is not aligned to
+    /// message boundaries.
+    pub max_name_length: Option<usize>,
+    /// Report the parts of a message that were parsed before an error (see
+    /// [ParseError::partial]). This requires copying the data when the
+    /// error occurs.
+    pub keep_partial: bool,
+    /// Protocol version to accept. In version 4, message IDs are not
+    /// recognised, so `?name[1]` is an error.
+    pub version: KatcpVersion,
+    /// Accept message IDs in brackets with leading zeros, such as
+    /// `?name[01]`, for compatibility with non-conforming peers. An ID
+    /// that is zero (such as `[0]` or `[00]`) is still rejected.
+    pub allow_leading_zero_mid: bool,
+    /// How to handle message IDs that are too large. The ID must still
+    /// consist only of digits.
+    pub on_mid_overflow: OnMidOverflow,
+    /// Treat `\r\n` as a single line terminator, instead of a line
+    /// terminated by `\r` followed by a blank line. This affects
+    /// [Parser::lines_seen] and [ParseIterator::with_blank_lines]. A `\r`
+    /// on its own still terminates a line.
+    ///
+    /// A message is returned as soon as its `\r` is seen, so the `\n` is
+    /// not part of the raw line from [ParseIterator::with_raw_lines], nor
+    /// of any other line.
+    pub merge_crlf: bool,
+    /// Which bytes separate the name, message ID and arguments. The other
+    /// whitespace byte is treated as part of an argument, and is invalid
+    /// elsewhere in a message. Lines containing only whitespace are blank
+    /// regardless.
+    pub separators: Separators,
 }
 
 /// Message parser.
@@ -83,20 +803,54 @@ struct Transient<'data> {
 pub struct Parser {
     /// Current state
     state: State,
+    /// State before the most recent transition (used to locate errors)
+    source_state: State,
     /// Number of characters seen on the current line (claimed to `max_line_length`)
     line_length: usize,
     /// Configured maximum line length
     max_line_length: usize,
+    /// Optional behaviour
+    options: ParserOptions,
+    /// Transition table for the protocol version
+    table: &'static ParserTable,
     /// Message type, or [None] if we haven't parsed it yet
     mtype: Option<MessageType>,
     /// Name (only allocated if [Parser::append] ends partway through the message)
     name: Vec<u8>,
     /// Message ID, or [None] if there isn't one or we haven't parsed one yet
     mid: Option<u32>,
+    /// Whether the message ID overflowed and was clamped (see [OnMidOverflow])
+    mid_overflowed: bool,
+    /// Whether the last line ended with `\r` (only with [ParserOptions::merge_crlf])
+    after_cr: bool,
     /// Fully-parsed arguments, excluding those in the current [Transient]
     arguments: Vec<Vec<u8>>,
     /// Current error, if we are in an error state
     error: Option<ParseError>,
+    /// Raw bytes of the current line that were received in previous chunks
+    /// (only when capturing raw lines)
+    raw_line: Vec<u8>,
+    /// Number of escape sequences in the current argument
+    escapes: usize,
+    /// See [MessageInfo::had_tab_separator]
+    had_tab_separator: bool,
+    /// See [MessageInfo::had_multiple_separators]
+    had_multiple_separators: bool,
+    /// Message types to return from the current call to [Parser::append_filtered]
+    type_filter: MessageTypeSet,
+    /// Number of lines that produced a message or an error
+    messages_parsed: u64,
+    /// Number of lines terminated, including blank and skipped lines
+    lines_seen: u64,
+    /// Number of bytes consumed
+    stream_offset: u64,
+    /// Value of `stream_offset` at the start of the current line
+    line_offset: u64,
+    /// Whether a [ParserIterator] that is not yet exhausted refers to the
+    /// parser (in which case the Python methods must not be used)
+    iterator_active: bool,
+    #[cfg(feature = "stats")]
+    stats: ParserStats,
 }
 
 /// Extend a `Cow<'_, [T]>` with new elements.
@@ -113,15 +867,87 @@ fn extend_cow<'a>(cow: &mut Cow<'a, [u8]>, elements: &'a [u8]) {
 impl Parser {
     /// Create a new parser.
     pub fn new(max_line_length: usize) -> Self {
+        Self::with_options(max_line_length, ParserOptions::default())
+    }
+
+    /// Create a new parser for a specific protocol version.
+    pub fn new_with_version(max_line_length: usize, version: KatcpVersion) -> Self {
+        let options = ParserOptions {
+            version,
+            ..Default::default()
+        };
+        Self::with_options(max_line_length, options)
+    }
+
+    /// Create a new parser with non-default options.
+    pub fn with_options(max_line_length: usize, options: ParserOptions) -> Self {
+        let table = match options.version {
+            KatcpVersion::V4 => &PARSER_TABLE_V4,
+            KatcpVersion::V5 => &PARSER_TABLE,
+        };
         Self {
             state: State::Start,
+            source_state: State::Start,
             line_length: 0,
             max_line_length,
+            options,
+            table,
             mtype: None,
             name: vec![],
             mid: None,
+            mid_overflowed: false,
+            after_cr: false,
             arguments: vec![],
             error: None,
+            raw_line: vec![],
+            escapes: 0,
+            had_tab_separator: false,
+            had_multiple_separators: false,
+            type_filter: MessageTypeSet::all(),
+            messages_parsed: 0,
+            lines_seen: 0,
+            stream_offset: 0,
+            line_offset: 0,
+            iterator_active: false,
+            #[cfg(feature = "stats")]
+            stats: ParserStats::default(),
+        }
+    }
+
+    /// Change the maximum line length.
+    ///
+    /// This takes effect immediately. If the line in progress is already
+    /// longer than the new limit, the next byte on the line is reported as
+    /// too long.
+    pub fn set_max_line_length(&mut self, max_line_length: usize) {
+        self.max_line_length = max_line_length;
+    }
+
+    /// Whether all the data so far has been consumed up to the end of a line.
+    ///
+    /// This is true when no partial line (not even whitespace) is buffered.
+    /// A `\r` ends a line, so it is already true before the `\n` of a
+    /// `\r\n` terminator arrives.
+    pub fn at_line_boundary(&self) -> bool {
+        self.state == State::Start
+    }
+
+    /// What the parser is in the middle of parsing.
+    pub fn current_state(&self) -> ParserPhase {
+        match self.state {
+            State::Start | State::EndOfLine | State::ErrorEndOfLine => ParserPhase::Idle,
+            State::Empty => ParserPhase::BlankLine,
+            State::BeforeName | State::Name => ParserPhase::Name,
+            State::BeforeId
+            | State::Id
+            | State::AfterId
+            | State::BeforeColonId
+            | State::ColonId => ParserPhase::Id,
+            State::BeforeArgument => ParserPhase::BeforeArgument,
+            State::Argument => ParserPhase::Argument,
+            State::ArgumentEscape => ParserPhase::Escape,
+            State::Skip => ParserPhase::Skip,
+            State::Error => ParserPhase::Error,
         }
     }
 
@@ -133,33 +959,201 @@ impl Parser {
         self.line_length
     }
 
+    /// Number of lines that produced either a message or a [ParseError].
+    ///
+    /// This is not cleared by [Parser::reset] (see [Parser::reset_counters]).
+    pub fn messages_parsed(&self) -> u64 {
+        self.messages_parsed
+    }
+
+    /// Number of lines terminated so far, including blank lines, lines
+    /// with errors and lines skipped by [Parser::append_filtered].
+    ///
+    /// Both `\r` and `\n` terminate a line, so a `\r\n` line ending
+    /// counts as a line followed by a blank line (unless
+    /// [ParserOptions::merge_crlf] is set). This is not cleared by
+    /// [Parser::reset] (see [Parser::reset_counters]).
+    pub fn lines_seen(&self) -> u64 {
+        self.lines_seen
+    }
+
+    /// Number of bytes consumed by [Parser::append] and related methods.
+    ///
+    /// This is not cleared by [Parser::reset] or [Parser::reset_counters].
+    pub fn stream_offset(&self) -> u64 {
+        self.stream_offset
+    }
+
+    /// Counts of how input bytes were consumed, for tuning the fast path.
+    ///
+    /// These are cleared by [Parser::reset_counters].
+    #[cfg(feature = "stats")]
+    pub fn stats(&self) -> ParserStats {
+        self.stats
+    }
+
+    /// Set [Parser::messages_parsed] and [Parser::lines_seen] back to zero.
+    pub fn reset_counters(&mut self) {
+        self.messages_parsed = 0;
+        self.lines_seen = 0;
+        #[cfg(feature = "stats")]
+        {
+            self.stats = ParserStats::default();
+        }
+    }
+
     /// Return the parser to its initial state.
+    ///
+    /// The counters are preserved; use [Parser::reset_counters] to clear
+    /// them as well.
     pub fn reset(&mut self) {
         self.state = State::Start;
         self.line_length = 0;
+        self.line_offset = self.stream_offset;
         self.mtype = None;
         self.name.clear();
         self.mid = None;
+        self.mid_overflowed = false;
+        self.after_cr = false;
         self.arguments.clear();
         self.error = None;
+        self.raw_line.clear();
+        self.escapes = 0;
+        self.had_tab_separator = false;
+        self.had_multiple_separators = false;
     }
 
-    /// Signal an error at a particular position on a line.
-    fn error_at(&mut self, transient: &mut Transient, message: impl Into<String>, position: usize) {
-        if self.state != State::ErrorEndOfLine {
-            self.state = State::Error;
-        }
-        if self.error.is_none() {
-            self.error = Some(ParseError::new(message.into(), position));
+    /// Save the current state so that it can later be passed to [Parser::restore].
+    ///
+    /// The checkpoint includes the partially-parsed message, if any, so it
+    /// may be taken between any two calls to [Parser::append]. Taking it at
+    /// a message boundary is cheapest, since there is nothing to copy.
+    pub fn checkpoint(&self) -> Checkpoint {
+        Checkpoint {
+            state: self.state,
+            line_length: self.line_length,
+            mtype: self.mtype,
+            name: self.name.clone(),
+            mid: self.mid,
+            mid_overflowed: self.mid_overflowed,
+            after_cr: self.after_cr,
+            arguments: self.arguments.clone(),
+            error: self.error.clone(),
+            raw_line: self.raw_line.clone(),
+            escapes: self.escapes,
+            had_tab_separator: self.had_tab_separator,
+            had_multiple_separators: self.had_multiple_separators,
+            messages_parsed: self.messages_parsed,
+            lines_seen: self.lines_seen,
+            stream_offset: self.stream_offset,
+            line_offset: self.line_offset,
         }
-        // Free up some memory early
-        self.arguments.clear();
-        transient.arguments.clear();
     }
 
-    /// Signal an error at the current position.
-    fn error(&mut self, transient: &mut Transient, message: impl Into<String>) {
-        self.error_at(transient, message, self.line_length + 1);
+    /// Return to the state saved by [Parser::checkpoint].
+    ///
+    /// Any data passed to the parser since the checkpoint is forgotten,
+    /// including a message in progress, and the counters return to their
+    /// values when the checkpoint was taken.
+    pub fn restore(&mut self, checkpoint: Checkpoint) {
+        self.state = checkpoint.state;
+        self.line_length = checkpoint.line_length;
+        self.mtype = checkpoint.mtype;
+        self.name = checkpoint.name;
+        self.mid = checkpoint.mid;
+        self.mid_overflowed = checkpoint.mid_overflowed;
+        self.after_cr = checkpoint.after_cr;
+        self.arguments = checkpoint.arguments;
+        self.error = checkpoint.error;
+        self.raw_line = checkpoint.raw_line;
+        self.escapes = checkpoint.escapes;
+        self.had_tab_separator = checkpoint.had_tab_separator;
+        self.had_multiple_separators = checkpoint.had_multiple_separators;
+        self.messages_parsed = checkpoint.messages_parsed;
+        self.lines_seen = checkpoint.lines_seen;
+        self.stream_offset = checkpoint.stream_offset;
+        self.line_offset = checkpoint.line_offset;
+    }
+
+    /// Signal an error at a particular position on a line.
+    fn error_at(&mut self, transient: &mut Transient, kind: ParseErrorKind, position: usize) {
+        let num_arguments = self.arguments.len() + transient.arguments.len();
+        let location = FieldLocation::from_state(self.source_state, num_arguments);
+        self.error_at_location(transient, kind, position, location);
+    }
+
+    /// Signal an error at a particular position and with an explicit location.
+    fn error_at_location(
+        &mut self,
+        transient: &mut Transient,
+        kind: ParseErrorKind,
+        position: usize,
+        location: FieldLocation,
+    ) {
+        if self.state != State::ErrorEndOfLine {
+            self.state = State::Error;
+        }
+        if self.error.is_none() {
+            let mut error = ParseError::new(kind, position, location);
+            error.line_offset = self.line_offset;
+            if self.options.keep_partial {
+                error.partial = self.partial(transient).map(Box::new);
+            }
+            self.error = Some(error);
+        }
+        // Free up some memory early. The raw line is kept, since it is
+        // reported with the error.
+        self.name.clear();
+        self.arguments.clear();
+        transient.name = Cow::default();
+        transient.arguments.clear();
+    }
+
+    /// Copy the completed parts of the current message, based on the state
+    /// in which an error occurred.
+    fn partial(&self, transient: &Transient) -> Option<PartialMessage> {
+        let mtype = self.mtype?;
+        let name_complete = !matches!(
+            self.source_state,
+            State::Start | State::Empty | State::BeforeName | State::Name
+        );
+        let mid_complete = matches!(
+            self.source_state,
+            State::AfterId | State::BeforeArgument | State::Argument | State::ArgumentEscape
+        );
+        let mut arguments: Vec<Vec<u8>> = self
+            .arguments
+            .iter()
+            .cloned()
+            .chain(transient.arguments.iter().map(|arg| arg.to_vec()))
+            .collect();
+        if matches!(self.source_state, State::Argument | State::ArgumentEscape) {
+            // The last argument was still in progress
+            arguments.pop();
+        }
+        Some(PartialMessage {
+            mtype,
+            name: name_complete.then(|| transient.name.to_vec()),
+            mid: self.mid.filter(|_| mid_complete),
+            arguments,
+        })
+    }
+
+    /// Signal an error at the current position.
+    fn error(&mut self, transient: &mut Transient, kind: ParseErrorKind) {
+        self.error_at(transient, kind, self.line_length.saturating_add(1));
+    }
+
+    /// Check whether the message may have a message ID, given its type.
+    fn check_id_allowed(&mut self, transient: &mut Transient, position: usize) {
+        if self.options.forbid_inform_id && self.mtype == Some(MessageType::Inform) {
+            self.error_at_location(
+                transient,
+                ParseErrorKind::InformIdNotAllowed,
+                position,
+                FieldLocation::Id,
+            );
+        }
     }
 
     /// Return the parser and a [Transient] to their initial states.
@@ -183,21 +1177,49 @@ impl Parser {
     ) -> Result<Option<ParsedMessage<'data>>, ParseError> {
         match action {
             Action::SetType(mtype) => {
-                self.mtype = Some(*mtype);
+                if self.type_filter.contains(*mtype) {
+                    self.mtype = Some(*mtype);
+                } else {
+                    self.state = State::Skip;
+                    transient.line_start = None;
+                }
             }
             Action::Name => {
                 extend_cow(&mut transient.name, chunk);
+                if let Some(max) = self.options.max_name_length {
+                    if transient.name.len() > max {
+                        // Report the first byte over the limit, which is
+                        // independent of how the input was split. The
+                        // name starts after the type.
+                        self.error_at(transient, ParseErrorKind::NameTooLong, max + 2);
+                    }
+                }
             }
             Action::Id => {
+                if self.mid.is_none() && chunk[0] == b'0' && !self.options.allow_leading_zero_mid {
+                    self.error_at(transient, ParseErrorKind::InvalidCharacter, position);
+                    return Ok(None);
+                }
+                if self.mid_overflowed {
+                    // Remaining digits of an ID that has been clamped
+                    return Ok(None);
+                }
                 // TODO: optimise this using the whole chunk at once
-                for ch in chunk.iter() {
+                for (i, ch) in chunk.iter().enumerate() {
                     // Compute the update in 64-bit to detect overflow at the end
                     let mid = self.mid.unwrap_or(0) as u64;
                     let mid = mid * 10 + ((*ch - b'0') as u64);
-                    if let Ok(value) = i32::try_from(mid) {
-                        self.mid = Some(value as u32);
+                    if mid <= MAX_MESSAGE_ID as u64 {
+                        self.mid = Some(mid as u32);
+                    } else if self.options.on_mid_overflow != OnMidOverflow::Error {
+                        self.mid = Some(MAX_MESSAGE_ID);
+                        self.mid_overflowed = true;
+                        break;
                     } else {
-                        self.error_at(transient, "Message ID overflowed", position);
+                        // Report the offending digit rather than the start
+                        // of the chunk, so that the position does not
+                        // depend on how the input was split.
+                        self.error_at(transient, ParseErrorKind::MessageIdOverflow, position + i);
                         break;
                     }
                 }
@@ -206,35 +1228,124 @@ impl Parser {
                 extend_cow(transient.arguments.last_mut().unwrap(), chunk);
             }
             Action::ArgumentEscaped(c) => {
-                transient.arguments.last_mut().unwrap().to_mut().push(*c);
+                // The chunk is the escape symbol, possibly followed by
+                // repetitions of the entire escape sequence.
+                let count = chunk.len().div_ceil(2);
+                self.escapes += count;
+                match self.options.max_escapes_per_argument {
+                    Some(max) if self.escapes > max => {
+                        // Report the backslash of the first escape sequence
+                        // over the limit. The chunk starts after the first
+                        // backslash.
+                        let index = count - (self.escapes - max);
+                        self.error_at(
+                            transient,
+                            ParseErrorKind::TooManyEscapes,
+                            position + 2 * index - 1,
+                        );
+                    }
+                    _ => {
+                        let argument = transient.arguments.last_mut().unwrap().to_mut();
+                        argument.resize(argument.len() + count, *c);
+                    }
+                }
             }
             Action::ResetLineLength => {
+                // A blank line has no raw line
+                transient.line_start = None;
+                self.raw_line.clear();
                 self.line_length = 0;
+                self.line_offset = self.stream_offset;
+                self.lines_seen += 1;
+                transient.blank_line = transient.report_blank_lines;
+            }
+            Action::StartId => {
+                self.check_id_allowed(transient, position);
+            }
+            Action::EndId => {
+                if self.mid == Some(0) {
+                    // Only possible with allow_leading_zero_mid
+                    self.error_at_location(
+                        transient,
+                        ParseErrorKind::InvalidCharacter,
+                        position,
+                        FieldLocation::Id,
+                    );
+                }
+            }
+            Action::ColonId => {
+                if !self.options.allow_colon_id {
+                    self.error_at(transient, ParseErrorKind::InvalidCharacter, position);
+                } else {
+                    self.check_id_allowed(transient, position);
+                }
+            }
+            Action::Separator => {
+                self.had_tab_separator |= chunk[0] == b'\t';
+            }
+            Action::ExtraSeparator => {
+                self.had_tab_separator |= chunk.contains(&b'\t');
+                self.had_multiple_separators = true;
+            }
+            Action::EndSkip => {
+                self.lines_seen += 1;
+                self.reset_transient(transient);
+                transient.line_start = None;
             }
             Action::Nothing => {}
             Action::Error => {
-                self.error_at(transient, "Invalid character", position);
+                let kind = match self.source_state {
+                    State::BeforeId if chunk[0] == b']' => ParseErrorKind::EmptyMessageId,
+                    State::ArgumentEscape => ParseErrorKind::BadEscape,
+                    _ => ParseErrorKind::InvalidCharacter,
+                };
+                self.error_at(transient, kind, position);
             }
         }
 
         match self.state {
             State::EndOfLine => {
-                let arguments: Vec<_> = std::mem::take(&mut self.arguments)
-                    .into_iter()
-                    .map(Cow::from)
-                    .chain(std::mem::take(&mut transient.arguments))
-                    .collect();
-                let msg = Message::new(
+                // Arguments from previous chunks go before those in this
+                // chunk. Reusing the transient vector lets
+                // ParseIterator::next_ref recycle it.
+                let mut arguments = std::mem::take(&mut transient.arguments);
+                if !self.arguments.is_empty() {
+                    arguments.splice(0..0, self.arguments.drain(..).map(Cow::from));
+                }
+                let drop_mid =
+                    self.mid_overflowed && self.options.on_mid_overflow == OnMidOverflow::Drop;
+                let msg: ParsedMessage = Message::new(
                     self.mtype.take().unwrap(),
                     std::mem::take(&mut transient.name),
-                    self.mid,
+                    self.mid.filter(|_| !drop_mid),
                     arguments,
                 );
+                // Part of the raw line from previous chunks, which
+                // next_message will complete
+                transient.raw_line = Cow::from(std::mem::take(&mut self.raw_line));
+                transient.info = MessageInfo {
+                    name_symbol: self
+                        .options
+                        .name_interner
+                        .as_ref()
+                        .and_then(|interner| interner.lookup(msg.name.as_ref())),
+                    wire_length: self.line_length,
+                    completed_at: self.options.on_message_time.map(|clock| clock()),
+                    had_tab_separator: self.had_tab_separator,
+                    had_multiple_separators: self.had_multiple_separators,
+                };
+                self.messages_parsed += 1;
+                self.lines_seen += 1;
                 self.reset_transient(transient);
                 Ok(Some(msg))
             }
             State::ErrorEndOfLine => {
                 let error = self.error.take().unwrap();
+                // Part of the raw line from previous chunks, which
+                // next_message will complete
+                transient.raw_line = Cow::from(std::mem::take(&mut self.raw_line));
+                self.messages_parsed += 1;
+                self.lines_seen += 1;
                 self.reset_transient(transient);
                 Err(error)
             }
@@ -242,6 +1353,21 @@ impl Parser {
         }
     }
 
+    /// Append the part of the line in this chunk to [Transient::raw_line],
+    /// once the line has ended just before `tail`.
+    fn complete_raw_line<'data>(&self, transient: &mut Transient<'data>, tail: &'data [u8]) {
+        if let Some(line_start) = transient.line_start.take() {
+            let line = &line_start[..line_start.len() - tail.len()];
+            extend_cow(&mut transient.raw_line, line);
+        }
+        let max = self.max_line_length;
+        match &mut transient.raw_line {
+            Cow::Borrowed(raw) if raw.len() > max => *raw = &raw[..max],
+            Cow::Owned(raw) => raw.truncate(max),
+            Cow::Borrowed(_) => {}
+        }
+    }
+
     /// Consume data until new end-of-line is seen, returning the message if any.
     fn next_message<'data>(
         &mut self,
@@ -252,50 +1378,144 @@ impl Parser {
         &'data [u8],
     ) {
         while !data.is_empty() {
-            if self.line_length >= self.max_line_length && self.state != State::Error {
-                self.error(transient, "Line too long");
+            if std::mem::take(&mut self.after_cr) && data[0] == b'\n' {
+                // Second half of a \r\n terminator
+                data = &data[1..];
+                self.stream_offset += 1;
+                self.line_offset = self.stream_offset;
+                continue;
+            }
+            self.source_state = self.state;
+            if self.line_length >= self.max_line_length
+                && self.state != State::Error
+                && self.state != State::Skip
+            {
+                self.error(transient, ParseErrorKind::LineTooLong);
             }
 
-            let entry = &PARSER_TABLE[self.state][data[0]];
+            let disabled_separator = self.options.separators.disabled();
+            let ch = match disabled_separator {
+                // Look up a byte that is valid only in arguments instead.
+                Some(disabled)
+                    if data[0] == disabled
+                        && !matches!(self.state, State::Start | State::Empty) =>
+                {
+                    b'\x01'
+                }
+                _ => data[0],
+            };
+            let mut entry = lookup(self.table, self.state, ch);
             if entry.create_argument {
-                transient.arguments.push(Cow::default());
+                let num_arguments = self.arguments.len() + transient.arguments.len();
+                if self
+                    .options
+                    .max_arguments
+                    .is_some_and(|max| num_arguments >= max)
+                {
+                    self.error(transient, ParseErrorKind::TooManyArguments);
+                    entry = lookup(self.table, self.state, ch);
+                } else {
+                    transient.arguments.push(Cow::default());
+                    self.escapes = 0;
+                }
+            }
+            if self.options.capture_raw_lines
+                && self.source_state == State::Start
+                && entry.state != State::Start
+            {
+                transient.line_start = Some(data);
             }
             self.state = entry.state;
             let mut p = 1; // number of bytes we're consuming this round
 
-            if let Some(fast_table) = &entry.fast_table {
-                // Find a sequence that we can add in one step. First compute a cap.
-                let max_len = if self.line_length >= self.max_line_length {
-                    data.len() // We're already in the error state
-                } else {
-                    std::cmp::min(data.len(), self.max_line_length - self.line_length)
-                };
-                while p < max_len && fast_table[data[p]] {
-                    p += 1;
+            // Find a sequence that we can add in one step. First compute a cap.
+            let max_len = if self.line_length >= self.max_line_length {
+                data.len() // We're already in the error state
+            } else {
+                std::cmp::min(data.len(), self.max_line_length - self.line_length)
+            };
+            // The fast table for a run of separators also accepts the
+            // disabled separator.
+            let fast_table = match entry.action {
+                Action::ExtraSeparator if disabled_separator.is_some() => None,
+                _ => entry.fast_table,
+            };
+            if let Some(fast_table) = fast_table {
+                p += find_rejected(fast_table, &data[p..max_len]);
+                #[cfg(feature = "stats")]
+                if p > 1 {
+                    self.stats.fast_path_runs += 1;
+                    self.stats.fast_path_bytes += (p - 1) as u64;
+                }
+            } else if let Action::ArgumentEscaped(_) = entry.action {
+                // The fast table can't merge escape sequences, but runs of
+                // the same escape sequence (such as many backslashes) can
+                // be handled together.
+                while p + 2 <= max_len && data[p] == b'\\' && data[p + 1] == data[0] {
+                    p += 2;
+                }
+                #[cfg(feature = "stats")]
+                {
+                    self.stats.escape_run_bytes += (p - 1) as u64;
                 }
             }
+            #[cfg(feature = "stats")]
+            {
+                self.stats.transitions += 1;
+            }
+
+            #[cfg(feature = "log")]
+            log::trace!(
+                "{:?} --{}--> {:?} ({:?}, {} bytes)",
+                self.source_state,
+                data[0].escape_ascii(),
+                self.state,
+                entry.action,
+                p
+            );
 
-            let position = self.line_length + 1;
+            let position = self.line_length.saturating_add(1);
             if self.line_length < self.max_line_length {
                 // The max_len calculation guarantees that this won't exceed
                 // max_line_length.
                 self.line_length += p;
             }
+            // Update this before applying the action, so that a line that
+            // ends here is followed by one starting after this chunk.
+            self.stream_offset += p as u64;
 
             let result = self.apply(&entry.action, &data[..p], transient, position);
+            if self.options.merge_crlf && self.state == State::Start && data[p - 1] == b'\r' {
+                self.after_cr = true;
+            }
             data = &data[p..];
 
             match result {
-                Ok(None) => {}
+                Ok(None) => {
+                    if transient.blank_line {
+                        // The caller checks the flag to tell this apart
+                        // from the end of the data.
+                        return (None, data);
+                    }
+                }
                 Ok(Some(msg)) => {
+                    self.complete_raw_line(transient, data);
                     return (Some(Ok(msg)), data);
                 }
                 Err(error) => {
+                    self.complete_raw_line(transient, data);
                     return (Some(Err(error)), data);
                 }
             }
         }
-        // Return any leftover state to the primary parser state
+        // Return any leftover state to the primary parser state. Only a line
+        // in error can exceed the maximum line length, and at most that much
+        // of it is reported.
+        if let Some(line_start) = transient.line_start.take() {
+            let room = self.max_line_length.saturating_sub(self.raw_line.len());
+            let len = std::cmp::min(line_start.len(), room);
+            self.raw_line.extend_from_slice(&line_start[..len]);
+        }
         self.name = std::mem::take(&mut transient.name).into_owned();
         self.arguments.extend(
             std::mem::take(&mut transient.arguments)
@@ -309,6 +1529,11 @@ impl Parser {
     ///
     /// The data is only consumed as a result of iteration. Dropping the
     /// iterator without fully consuming it has undefined results.
+    ///
+    /// A parse error does not end iteration: the rest of the offending line
+    /// is discarded and parsing resumes at the next line. Callers that want
+    /// to stop at the first error should stop iterating and call
+    /// [Parser::reset] before reusing the parser.
     #[must_use = "Must consume the returned iterator for anything to happen"]
     pub fn append<'parser, 'data, D>(
         &'parser mut self,
@@ -317,19 +1542,373 @@ impl Parser {
     where
         D: AsRef<[u8]> + ?Sized,
     {
+        self.append_filtered(data, MessageTypeSet::all())
+    }
+
+    /// Add data to the parser and return all the messages that arise.
+    ///
+    /// This is equivalent to collecting the iterator returned by
+    /// [Parser::append]. It allocates a vector for the results, so use
+    /// [Parser::append] directly to process messages without allocating.
+    pub fn parse_all<'data>(
+        &mut self,
+        data: &'data [u8],
+    ) -> Vec<Result<ParsedMessage<'data>, ParseError>> {
+        self.append(data).collect()
+    }
+
+    /// Add data to the parser, returning only messages with a type in `types`.
+    ///
+    /// Lines with other types are skipped without being stored or
+    /// validated, so they produce neither messages nor errors. The filter
+    /// is applied when the type character is seen, so it does not affect a
+    /// message that was started by a previous call.
+    #[must_use = "Must consume the returned iterator for anything to happen"]
+    pub fn append_filtered<'parser, 'data, D>(
+        &'parser mut self,
+        data: &'data D,
+        types: MessageTypeSet,
+    ) -> ParseIterator<'parser, 'data>
+    where
+        D: AsRef<[u8]> + ?Sized,
+    {
+        self.type_filter = types;
+        let data = data.as_ref();
+        let transient = self.new_transient(data);
+        ParseIterator {
+            parser: self,
+            data,
+            transient,
+            current: None,
+        }
+    }
+
+    /// Add data from several buffers to the parser, as if they were
+    /// concatenated and passed to [Parser::append].
+    ///
+    /// This is useful with vectored (scatter) reads. Messages may span
+    /// buffer boundaries. Due to limitations of [IoSlice], the returned
+    /// messages borrow from `slices` rather than the underlying buffers.
+    #[must_use = "Must consume the returned iterator for anything to happen"]
+    pub fn append_vectored<'parser, 'data>(
+        &'parser mut self,
+        slices: &'data [IoSlice<'_>],
+    ) -> VectoredParseIterator<'parser, 'data> {
+        // Start with an empty slice; the iterator moves on to the real
+        // slices as each is exhausted.
+        VectoredParseIterator {
+            inner: self.append(&[]),
+            slices,
+        }
+    }
+
+    /// Add data held in a shared buffer to the parser, producing messages
+    /// whose fields share that buffer rather than borrowing from it.
+    ///
+    /// `share` is called with each field that lies within `data` and must
+    /// return a handle to that part of the buffer. For example, for a
+    /// reference-counted `bytes::Bytes` buffer `buf`, pass `&buf` and
+    /// `|field| buf.slice_ref(field)`. The messages can then outlive the
+    /// borrow of `buf`, and fields without escape sequences are not copied.
+    /// Fields that need unescaping, or that started in an earlier call, are
+    /// copied into a [SharedField::Owned].
+    ///
+    /// Otherwise this behaves like [Parser::append].
+    #[must_use = "Must consume the returned iterator for anything to happen"]
+    pub fn append_shared<'parser, 'data, B, F>(
+        &'parser mut self,
+        data: &'data [u8],
+        share: F,
+    ) -> SharedParseIterator<'parser, 'data, F>
+    where
+        B: AsRef<[u8]>,
+        F: FnMut(&'data [u8]) -> B,
+    {
+        SharedParseIterator {
+            inner: self.append(data),
+            data,
+            share,
+        }
+    }
+
+    /// Signal the end of the input.
+    ///
+    /// A partial line is completed as if a newline had been received, and
+    /// the resulting message or error (if any) is returned. A line that
+    /// ends where a newline would not be valid (partway through an escape
+    /// sequence, or before the name or message ID is complete) is reported
+    /// as [ParseErrorKind::UnterminatedEscape] or
+    /// [ParseErrorKind::UnexpectedEnd]. The parser is left in its initial
+    /// state.
+    pub fn finish(&mut self) -> Option<Result<ParsedMessage<'static>, ParseError>> {
+        let truncated = match self.state {
+            State::Start => return None,
+            // Report the position of the backslash
+            State::ArgumentEscape => Some((ParseErrorKind::UnterminatedEscape, self.line_length)),
+            State::BeforeName | State::BeforeId | State::Id | State::BeforeColonId => Some((
+                ParseErrorKind::UnexpectedEnd,
+                self.line_length.saturating_add(1),
+            )),
+            _ => None,
+        };
+        if let Some((kind, position)) = truncated {
+            let location = FieldLocation::from_state(self.state, self.arguments.len());
+            let mut error = ParseError::new(kind, position, location);
+            error.line_offset = self.line_offset;
+            self.reset();
+            self.messages_parsed += 1;
+            self.lines_seen += 1;
+            return Some(Err(error));
+        }
+        // The newline is not part of the stream, so don't count it
+        let stream_offset = self.stream_offset;
+        let result = self.append(b"\n").next();
+        debug_assert!(self.state == State::Start);
+        self.stream_offset = stream_offset;
+        self.line_offset = stream_offset;
+        result
+    }
+
+    /// Create a [Transient] for parsing `data`.
+    ///
+    /// State for a partial message is moved from the parser.
+    fn new_transient<'data>(&mut self, data: &'data [u8]) -> Transient<'data> {
         let mut transient = Transient {
             name: Cow::from(std::mem::take(&mut self.name)),
             arguments: Default::default(),
+            line_start: None,
+            raw_line: Cow::default(),
+            info: MessageInfo::default(),
+            report_blank_lines: false,
+            blank_line: false,
         };
+        // If a line is in progress, the raw line continues from the start
+        // of the chunk.
+        if self.options.capture_raw_lines && !matches!(self.state, State::Start | State::Skip) {
+            transient.line_start = Some(data);
+        }
         // If there is at least one argument in the state, transfer the last
         // one to the Transient so that it can be extended.
         if let Some(last_arg) = self.arguments.pop() {
             transient.arguments.push(Cow::from(last_arg));
         }
-        ParseIterator {
-            parser: self,
-            data: data.as_ref(),
-            transient,
+        transient
+    }
+}
+
+/// Unescape a single argument value, as it would appear in a message.
+///
+/// This applies the same rules as the parser, using the same tables. The
+/// input must not contain unescaped whitespace or newlines. Positions in
+/// errors are relative to the start of `escaped`. An empty input gives an
+/// empty value, just like `\@`.
+pub fn unescape_argument(escaped: &[u8]) -> Result<Vec<u8>, ParseError> {
+    let mut out = Vec::with_capacity(escaped.len());
+    let mut state = State::BeforeArgument;
+    for (i, &c) in escaped.iter().enumerate() {
+        let entry = lookup(&PARSER_TABLE, state, c);
+        match (&entry.action, entry.state) {
+            (Action::Argument, _) => out.push(c),
+            (Action::ArgumentEscaped(value), _) => out.push(*value),
+            (Action::Nothing, State::Argument | State::ArgumentEscape) => {}
+            _ => {
+                let kind = match state {
+                    State::ArgumentEscape => ParseErrorKind::BadEscape,
+                    _ => ParseErrorKind::InvalidCharacter,
+                };
+                return Err(ParseError::new(kind, i + 1, FieldLocation::Argument(0)));
+            }
+        }
+        state = entry.state;
+    }
+    if state == State::ArgumentEscape {
+        // Report the position of the backslash
+        return Err(ParseError::new(
+            ParseErrorKind::UnterminatedEscape,
+            escaped.len(),
+            FieldLocation::Argument(0),
+        ));
+    }
+    Ok(out)
+}
+
+/// Size of the buffer used by [read_messages]
+const READ_BUFFER_SIZE: usize = 65536;
+
+/// Error produced by [read_messages].
+#[derive(Error, Debug)]
+pub enum ReadError {
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+}
+
+/// Iterator returned by [read_messages].
+pub struct ReadMessages<R: Read> {
+    reader: R,
+    parser: Parser,
+    buffer: Vec<u8>,
+    /// Start of the data in `buffer` not yet passed to the parser
+    offset: usize,
+    /// End of the valid data in `buffer`
+    end: usize,
+    /// Whether the end of the input (or an I/O error) has been reached
+    done: bool,
+}
+
+impl<R: Read> Iterator for ReadMessages<R> {
+    type Item = Result<OwnedMessage, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while !self.done {
+            if self.offset < self.end {
+                let mut iter = self.parser.append(&self.buffer[self.offset..self.end]);
+                let result = iter.next();
+                self.offset = self.end - iter.data.len();
+                if let Some(result) = result {
+                    return Some(result.map(Message::into_owned).map_err(ReadError::from));
+                }
+            }
+            match self.reader.read(&mut self.buffer) {
+                Ok(0) => {
+                    self.done = true;
+                    return self
+                        .parser
+                        .finish()
+                        .map(|result| result.map(Message::into_owned).map_err(ReadError::from));
+                }
+                Ok(n) => {
+                    self.offset = 0;
+                    self.end = n;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err.into()));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Parse all the messages from a reader, such as a file or stdin.
+///
+/// The reader is read in large chunks, so it does not need to be buffered.
+/// At the end of the input, a final unterminated line is handled by
+/// [Parser::finish]. Iteration stops after an I/O error (other than
+/// [io::ErrorKind::Interrupted], which is retried).
+pub fn read_messages<R: Read>(reader: R, max_line_length: usize) -> ReadMessages<R> {
+    ReadMessages {
+        reader,
+        parser: Parser::new(max_line_length),
+        buffer: vec![0; READ_BUFFER_SIZE],
+        offset: 0,
+        end: 0,
+        done: false,
+    }
+}
+
+impl<'data> Message<Cow<'data, [u8]>, Cow<'data, [u8]>> {
+    /// Parse a single complete message, including its terminator.
+    ///
+    /// This is a convenience for when the framing is already known. It
+    /// fails with [ParseErrorKind::UnexpectedEnd] if the line is not
+    /// terminated, and with [ParseErrorKind::TrailingData] if there is
+    /// anything after the terminator (other than the `\n` of a `\r\n`
+    /// terminator). The error for trailing data is located at the start of
+    /// the next line.
+    pub fn from_bytes(data: &'data [u8]) -> Result<Self, ParseError> {
+        let mut parser = Parser::new(data.len());
+        let mut iter = parser.append(data);
+        let Some(result) = iter.next() else {
+            let location = FieldLocation::from_state(parser.state, parser.arguments.len());
+            return Err(ParseError::new(
+                ParseErrorKind::UnexpectedEnd,
+                data.len() + 1,
+                location,
+            ));
+        };
+        let message = result?;
+        let mut end = data.len() - iter.data.len();
+        if data[end - 1] == b'\r' && data.get(end) == Some(&b'\n') {
+            end += 1;
+        }
+        if end == data.len() {
+            Ok(message)
+        } else {
+            let mut error = ParseError::new(ParseErrorKind::TrailingData, 1, FieldLocation::Type);
+            error.line_offset = end as u64;
+            Err(error)
+        }
+    }
+}
+
+/// Python iterator returned by `Parser.iter_append`.
+///
+/// It holds a reference to the data, and parses one message from it each
+/// time it is advanced. Until it is exhausted or dropped, the other Python
+/// methods of the parser raise [RuntimeError](PyRuntimeError), since
+/// interleaving them would reorder the stream.
+#[pyclass(module = "katcp_codec._lib")]
+pub struct ParserIterator {
+    parser: Py<Parser>,
+    data: Py<PyBytes>,
+    /// Number of bytes of `data` already passed to the parser
+    offset: usize,
+    /// Whether the iterator has not yet been exhausted
+    active: bool,
+}
+
+impl Drop for ParserIterator {
+    fn drop(&mut self) {
+        if self.active {
+            Python::with_gil(|py| {
+                if let Ok(mut parser) = self.parser.try_borrow_mut(py) {
+                    parser.iterator_active = false;
+                }
+            });
+        }
+    }
+}
+
+#[pymethods]
+impl ParserIterator {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        if !self.active {
+            return Ok(None);
+        }
+        let data = self.data.as_bytes(py);
+        let mut parser = self.parser.borrow_mut(py);
+        let mut iter = parser.append(&data[self.offset..]);
+        let result = iter.next();
+        self.offset = data.len() - iter.data.len();
+        match result {
+            None => {
+                self.active = false;
+                parser.iterator_active = false;
+                Ok(None)
+            }
+            Some(Ok(msg)) => Ok(Some(msg.to_object(py))),
+            Some(Err(error)) => Err(PyErr::from_value_bound(error.to_py(py)?.into_bound(py))),
+        }
+    }
+}
+
+impl Parser {
+    /// Fail if a [ParserIterator] is still feeding data to the parser.
+    fn check_no_iterator(&self) -> PyResult<()> {
+        if self.iterator_active {
+            Err(PyRuntimeError::new_err(
+                "Parser cannot be used until the iterator from iter_append is exhausted",
+            ))
+        } else {
+            Ok(())
         }
     }
 }
@@ -344,30 +1923,69 @@ impl Parser {
     // TODO: support buffer protocol?
     #[pyo3(name = "append")]
     fn py_append<'py>(&mut self, data: &Bound<'py, PyBytes>) -> PyResult<Bound<'py, PyList>> {
+        self.check_no_iterator()?;
         let py = data.py();
+        let data = data.as_bytes();
+        // Parsing doesn't need the GIL, so release it while parsing and
+        // only reacquire it to build the Python objects. `self` stays
+        // borrowed meanwhile, so another thread using the same parser gets
+        // a RuntimeError rather than waiting.
+        let results: Vec<_> = py.allow_threads(|| self.append(data).collect());
         let out = PyList::empty_bound(py);
-        for result in self.append(data.as_bytes()) {
+        for result in results {
             match result {
                 Ok(msg) => {
                     out.append(msg)?;
                 }
                 Err(error) => {
-                    out.append(PyValueError::new_err(error.to_string()).into_value(py))?;
+                    out.append(error.to_py(py)?)?;
                 }
             }
         }
         Ok(out)
     }
 
+    #[pyo3(name = "iter_append")]
+    fn py_iter_append(slf: Bound<'_, Self>, data: Bound<'_, PyBytes>) -> PyResult<ParserIterator> {
+        {
+            let mut parser = slf.borrow_mut();
+            parser.check_no_iterator()?;
+            parser.iterator_active = true;
+        }
+        Ok(ParserIterator {
+            parser: slf.unbind(),
+            data: data.unbind(),
+            offset: 0,
+            active: true,
+        })
+    }
+
+    #[pyo3(name = "finish")]
+    fn py_finish(&mut self, py: Python<'_>) -> PyResult<Option<PyObject>> {
+        self.check_no_iterator()?;
+        match self.finish() {
+            None => Ok(None),
+            Some(Ok(msg)) => Ok(Some(msg.to_object(py))),
+            Some(Err(error)) => Ok(Some(error.to_py(py)?)),
+        }
+    }
+
     #[pyo3(name = "reset")]
-    fn py_reset(&mut self) {
+    fn py_reset(&mut self) -> PyResult<()> {
+        self.check_no_iterator()?;
         self.reset();
+        Ok(())
     }
 
     #[getter(buffer_size)]
     fn py_buffer_size(&self) -> usize {
         self.buffer_size()
     }
+
+    #[getter(stream_offset)]
+    fn py_stream_offset(&self) -> u64 {
+        self.stream_offset()
+    }
 }
 
 #[cfg(test)]
@@ -378,6 +1996,14 @@ mod test {
     use proptest::prelude::*;
     use rstest::*;
 
+    /// Set the stream offset of the line containing an error
+    fn at_line(error: ParseError, line_offset: u64) -> ParseError {
+        ParseError {
+            line_offset,
+            ..error
+        }
+    }
+
     /// Helper macro for constructing messages for comparison
     macro_rules! msg {
         ( $mtype:expr, $name:literal, $mid:expr ) => {
@@ -445,8 +2071,54 @@ mod test {
         assert_eq!(messages.as_slice(), &[Ok(message)]);
     }
 
+    #[rstest]
+    #[case(b"?foo\n", 0)]
+    #[case(b"?foo \n", 0)]
+    #[case(b"?foo\t \n", 0)]
+    #[case(b"?foo[1] \n", 0)]
+    #[case(b"?foo \\@\n", 1)]
+    #[case(b"?foo \\@ \n", 1)]
+    #[case(b"?foo[1] \\@\n", 1)]
+    #[case(b"?foo \\@ \\@\n", 2)]
+    fn test_empty_arguments(#[case] input: &[u8], #[case] count: usize, mut parser: Parser) {
+        let messages: Vec<_> = parser.append(input).collect();
+        let [Ok(message)] = messages.as_slice() else {
+            panic!("expected a single message, got {messages:?}");
+        };
+        assert_eq!(message.arguments.len(), count);
+        assert_eq!(message.is_empty_args(), count == 0);
+        assert!(message.arguments.iter().all(|arg| arg.is_empty()));
+    }
+
+    #[rstest]
+    #[case(b"?foo a \n", &["a"])]
+    #[case(b"?foo a\t\r", &["a"])]
+    #[case(b"?foo a \\@\n", &["a", ""])]
+    #[case(b"?foo a \\@ \n", &["a", ""])]
+    #[case(b"?foo \\@ a\n", &["", "a"])]
+    #[case(b"?foo a \\@ \\@\n", &["a", "", ""])]
+    fn test_trailing_empty_argument(
+        #[case] input: &[u8],
+        #[case] arguments: &[&str],
+        mut parser: Parser,
+    ) {
+        let messages: Vec<_> = parser.append(input).collect();
+        let [Ok(message)] = messages.as_slice() else {
+            panic!("expected a single message, got {messages:?}");
+        };
+        let arguments: Vec<&[u8]> = arguments.iter().map(|arg| arg.as_bytes()).collect();
+        assert_eq!(message.arguments, arguments);
+        assert_eq!(message.arg_count(), arguments.len());
+        // The argument count survives formatting and parsing again
+        let encoded = message.to_vec();
+        let reparsed: Vec<_> = parser.append(&encoded).collect();
+        assert_eq!(reparsed.as_slice(), &[Ok(message.clone())]);
+    }
+
     #[rstest]
     #[case(b" ?leading-space\n")]
+    #[case(b"   !leading-spaces\n")]
+    #[case(b"\t#leading-tab\n")]
     #[case(b"no-message-type\n")]
     #[case(b"?0\n")]
     #[case(b"?A_\n")]
@@ -466,46 +2138,1372 @@ mod test {
         assert!(matches!(messages.as_slice(), &[Err(_)]));
     }
 
-    #[test]
-    fn test_too_long() {
-        let mut parser = Parser::new(10);
-        let messages: Vec<_> = parser.append(&b"?hello1234\n").collect();
+    #[fixture]
+    fn colon_parser() -> Parser {
+        let options = ParserOptions {
+            allow_colon_id: true,
+            ..Default::default()
+        };
+        Parser::with_options(usize::MAX, options)
+    }
+
+    #[rstest]
+    #[case(b"?mid[1234]\n", msg!(Request, b"mid", Some(1234)))]
+    #[case(b"?mid:1234\n", msg!(Request, b"mid", Some(1234)))]
+    #[case(b"!mid:56 ok\n", msg!(Reply, b"mid", Some(56), b"ok"))]
+    #[case(b"#mid:7\t\r", msg!(Inform, b"mid", Some(7)))]
+    fn test_colon_id(
+        #[case] input: &[u8],
+        #[case] message: ParsedMessage,
+        mut colon_parser: Parser,
+    ) {
+        let messages: Vec<_> = colon_parser.append(input).collect();
+        assert_eq!(messages.as_slice(), &[Ok(message)]);
+    }
+
+    #[rstest]
+    #[case(b"?mid[01]\n", false, Err(6))]
+    #[case(b"?mid[0]\n", false, Err(6))]
+    #[case(b"?mid[10]\n", false, Ok(10))]
+    #[case(b"?mid[01]\n", true, Ok(1))]
+    #[case(b"?mid[000123]\n", true, Ok(123))]
+    #[case(b"?mid[0]\n", true, Err(7))]
+    #[case(b"?mid[00]\n", true, Err(8))]
+    fn test_leading_zero_mid(
+        #[case] input: &[u8],
+        #[case] allow_leading_zero_mid: bool,
+        #[case] expected: Result<u32, usize>,
+    ) {
+        let options = ParserOptions {
+            allow_leading_zero_mid,
+            ..Default::default()
+        };
+        let expected = expected
+            .map(|mid| msg!(Request, b"mid", Some(mid)))
+            .map_err(|position| {
+                ParseError::new(
+                    ParseErrorKind::InvalidCharacter,
+                    position,
+                    FieldLocation::Id,
+                )
+            });
+        // Check every split point, to ensure the result does not depend on
+        // how the input is chunked.
+        for split in 0..input.len() {
+            let mut parser = Parser::with_options(1000, options.clone());
+            let mut messages: Vec<_> = parser.append(&input[..split]).collect();
+            messages.extend(parser.append(&input[split..]));
+            assert_eq!(messages.len(), 1);
+            assert_eq!(messages[0], expected);
+        }
+    }
+
+    #[rstest]
+    #[case(b"?mid:\n", 6)]
+    #[case(b"?mid: arg\n", 6)]
+    #[case(b"?mid:0\n", 6)]
+    #[case(b"?mid:1]\n", 7)]
+    #[case(b"?mid:1[2]\n", 7)]
+    #[case(b"?mid[1]:2\n", 8)]
+    fn test_colon_id_fail(#[case] input: &[u8], #[case] position: usize, mut colon_parser: Parser) {
+        let messages: Vec<_> = colon_parser.append(input).collect();
         assert_eq!(
             messages.as_slice(),
-            &[Err(ParseError::new("Line too long", 11))]
+            &[Err(ParseError::new(
+                ParseErrorKind::InvalidCharacter,
+                position,
+                FieldLocation::Id
+            ))]
         );
-        let messages: Vec<_> = parser.append(&b"?hello123\n").collect();
-        assert_eq!(messages.as_slice(), &[Ok(msg!(Request, b"hello123", None))]);
     }
 
-    fn split_points_strategy(size: usize) -> impl Strategy<Value = Vec<usize>> {
-        prop::collection::vec(1..(size - 1), 1..10).prop_map(move |mut x| {
-            x.push(0);
-            x.push(size);
-            x.sort();
-            x
-        })
+    #[rstest]
+    fn test_colon_id_disabled(mut parser: Parser) {
+        let messages: Vec<_> = parser.append(b"?mid:1234\n").collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[Err(ParseError::new(
+                ParseErrorKind::InvalidCharacter,
+                5,
+                FieldLocation::Name
+            ))]
+        );
     }
 
-    /// Strategy that produces a message and some points at which to cut it.
-    fn split_message_strategy() -> impl Strategy<Value = (String, Vec<usize>)> {
-        text_message_strategy().prop_flat_map(|x| {
-            let len = x.as_bytes().len();
-            (Just(x), split_points_strategy(len))
-        })
+    #[test]
+    fn test_error_display() {
+        let error = ParseError::new(ParseErrorKind::LineTooLong, 7, FieldLocation::Name);
+        assert_eq!(error.kind(), ParseErrorKind::LineTooLong);
+        assert_eq!(error.to_string(), "\"Line too long\" at character 7");
     }
 
-    proptest! {
-        /// Test that a variety of valid messages parse successfully
-        #[test]
-        fn success(input in text_message_strategy()) {
-            let mut parser = Parser::new(usize::MAX);
-            let messages: Vec<_> = parser.append(input.as_bytes()).collect();
-            assert!(matches!(messages.as_slice(), &[Ok(_)]));
+    #[rstest]
+    #[case(b"?foo[]\n", ParseErrorKind::EmptyMessageId, 6, FieldLocation::Id)]
+    #[case(b"?foo[] x\n", ParseErrorKind::EmptyMessageId, 6, FieldLocation::Id)]
+    #[case(b"?foo[x]\n", ParseErrorKind::InvalidCharacter, 6, FieldLocation::Id)]
+    #[case(
+        b"?foo \\x\n",
+        ParseErrorKind::BadEscape,
+        7,
+        FieldLocation::Argument(0)
+    )]
+    #[case(
+        b"?foo a b\\\n",
+        ParseErrorKind::BadEscape,
+        10,
+        FieldLocation::Argument(1)
+    )]
+    #[case(
+        b"?foo \\\\x\0\n",
+        ParseErrorKind::InvalidCharacter,
+        9,
+        FieldLocation::Argument(0)
+    )]
+    fn test_error_kind(
+        #[case] input: &[u8],
+        #[case] kind: ParseErrorKind,
+        #[case] position: usize,
+        #[case] location: FieldLocation,
+    ) {
+        for split in 0..=input.len() {
+            let mut parser = Parser::new(100);
+            let mut messages: Vec<_> = parser.append(&input[..split]).collect();
+            messages.extend(parser.append(&input[split..]));
+            assert_eq!(
+                messages.as_slice(),
+                &[Err(ParseError::new(kind, position, location))],
+                "split at {split}"
+            );
         }
+    }
 
-        /// Test that splitting a message doesn't change how it is parsed
-        #[test]
+    #[rstest]
+    #[case(ParseErrorKind::EmptyMessageId, "Invalid character")]
+    #[case(ParseErrorKind::BadEscape, "Invalid character")]
+    fn test_error_kind_display(#[case] kind: ParseErrorKind, #[case] expected: &str) {
+        assert_eq!(kind.to_string(), expected);
+    }
+
+    #[test]
+    fn test_version_4() {
+        let mut parser = Parser::new_with_version(1000, KatcpVersion::V4);
+        let messages: Vec<_> = parser.append(b"?foo[1]\n").collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[Err(ParseError::new(
+                ParseErrorKind::InvalidCharacter,
+                5,
+                FieldLocation::Name
+            ))]
+        );
+        // The colon syntax is also rejected, even if enabled
+        let options = ParserOptions {
+            version: KatcpVersion::V4,
+            allow_colon_id: true,
+            ..Default::default()
+        };
+        let mut parser = Parser::with_options(1000, options);
+        let messages: Vec<_> = parser.append(b"?foo a[1]\n?foo[1] a\n#foo:1\n").collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[
+                Ok(msg!(Request, b"foo", None, b"a[1]")),
+                Err(at_line(
+                    ParseError::new(ParseErrorKind::InvalidCharacter, 5, FieldLocation::Name),
+                    10
+                )),
+                Err(at_line(
+                    ParseError::new(ParseErrorKind::InvalidCharacter, 5, FieldLocation::Name),
+                    20
+                )),
+            ]
+        );
+    }
+
+    #[rstest]
+    fn test_checkpoint(mut parser: Parser) {
+        let messages: Vec<_> = parser.append(b"?first\n").collect();
+        assert_eq!(messages.len(), 1);
+        let checkpoint = parser.checkpoint();
+        let messages: Vec<_> = parser.append(b"?second bad\0 ").collect();
+        assert!(messages.is_empty());
+        assert!(checkpoint.at_boundary());
+        assert!(!parser.checkpoint().at_boundary());
+
+        parser.restore(checkpoint);
+        assert_eq!(parser.buffer_size(), 0);
+        let messages: Vec<_> = parser.append(b"?second good\n").collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[Ok(msg!(Request, b"second", None, b"good"))]
+        );
+    }
+
+    /// A checkpoint taken partway through a message restores the message.
+    #[rstest]
+    #[case(b"?first[5] a ", b"b\\\\c d\n")]
+    #[case(b"?first[5] a b\\\\", b"c d\n")]
+    #[case(b"?first[5] a b\\", b"\\c d\n")]
+    #[case(b"?fir", b"st[5] a b\\\\c d\n")]
+    fn test_checkpoint_partial(mut parser: Parser, #[case] head: &[u8], #[case] tail: &[u8]) {
+        parser.append(b"?zero\n").for_each(drop);
+        parser.append(head).for_each(drop);
+        let checkpoint = parser.checkpoint();
+        assert!(!checkpoint.at_boundary());
+        let messages: Vec<_> = parser.append(b"\0garbage\n").collect();
+        assert_eq!(messages.len(), 1);
+
+        parser.restore(checkpoint);
+        assert_eq!(parser.messages_parsed(), 1);
+        assert_eq!(parser.stream_offset(), 6 + head.len() as u64);
+        let messages: Vec<_> = parser.append(tail).collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[Ok(msg!(Request, b"first", Some(5), b"a", b"b\\c", b"d"))]
+        );
+    }
+
+    #[rstest]
+    #[case(b"?first\n\r\n?bad\0x\n", 13)]
+    #[case(b"?first\n?second[1] x\n#toolongname\n", 30)]
+    #[case(b"?first\n?bad\0\n?bad\0\n", 17)]
+    fn test_stream_position(#[case] input: &[u8], #[case] expected: u64) {
+        for split in 0..=input.len() {
+            let mut parser = Parser::new(10);
+            let mut errors: Vec<_> = parser.append(&input[..split]).collect();
+            errors.extend(parser.append(&input[split..]));
+            let error = errors.pop().unwrap().unwrap_err();
+            assert_eq!(error.stream_position(), expected, "split at {split}");
+            assert_eq!(parser.stream_offset(), input.len() as u64);
+        }
+    }
+
+    #[rstest]
+    fn test_stream_position_finish(mut parser: Parser) {
+        parser.append(b"?first\n?second x\\").for_each(drop);
+        let error = parser.finish().unwrap().unwrap_err();
+        assert_eq!(error.stream_position(), 16);
+        assert_eq!(parser.stream_offset(), 17);
+        let error = parser.append(b"?bad\0\n").next().unwrap().unwrap_err();
+        assert_eq!(error.stream_position(), 21);
+    }
+
+    #[rstest]
+    fn test_next_ref(mut parser: Parser) {
+        let input = b"?first a b c\n?bad\0\n#second x\n!third";
+        let expected: Vec<_> = Parser::new(1000).append(input).collect();
+        let mut iter = parser.append(input);
+        let mut results = vec![];
+        let mut pointers = vec![];
+        while let Some(result) = iter.next_ref() {
+            if let Ok(msg) = &result {
+                pointers.push(msg.arguments.as_ptr());
+            }
+            results.push(result.cloned());
+        }
+        assert_eq!(results, expected);
+        // The argument vector was reused for the second message
+        assert_eq!(pointers[0], pointers[1]);
+
+        // Arguments split across calls are kept in order
+        let mut iter = parser.append(b" y z\n");
+        let expected: ParsedMessage = msg!(Reply, b"third", None, b"y", b"z");
+        assert_eq!(iter.next_ref().unwrap().unwrap(), &expected);
+    }
+
+    #[rstest]
+    fn test_counters(mut parser: Parser) {
+        let messages: Vec<_> = parser.append(b"?first\n\n  \n?bad\0\n").collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(parser.messages_parsed(), 2);
+        assert_eq!(parser.lines_seen(), 4);
+        let checkpoint = parser.checkpoint();
+
+        // Skipped lines are seen but not parsed
+        let messages: Vec<_> = parser
+            .append_filtered(b"#skipped\n#x\n", MessageType::Request.into())
+            .collect();
+        assert!(messages.is_empty());
+        assert_eq!(parser.messages_parsed(), 2);
+        assert_eq!(parser.lines_seen(), 6);
+
+        parser.reset();
+        assert_eq!(parser.lines_seen(), 6);
+        parser.restore(checkpoint);
+        assert_eq!(parser.messages_parsed(), 2);
+        assert_eq!(parser.lines_seen(), 4);
+        parser.reset_counters();
+        assert_eq!(parser.messages_parsed(), 0);
+        assert_eq!(parser.lines_seen(), 0);
+    }
+
+    #[rstest]
+    #[case(
+        b"?escapes \\\\\\\\\\\\ \\_\\_\\t\\_ x\\0\\0\\\\0\n",
+        msg!(Request, b"escapes", None, b"\\\\\\", b"  \t ", b"x\0\0\\0"),
+    )]
+    #[case(
+        b"?escapes \\\\\\\\\\\\\\\\\\\\\\_\n",
+        msg!(Request, b"escapes", None, b"\\\\\\\\\\ "),
+    )]
+    fn test_repeated_escapes(#[case] input: &[u8], #[case] message: ParsedMessage) {
+        // Try with every line length limit, to check that the limit is not
+        // overrun while merging escape sequences.
+        for max_line_length in 1..=input.len() {
+            let mut parser = Parser::new(max_line_length);
+            let messages: Vec<_> = parser.append(input).collect();
+            if max_line_length < input.len() {
+                // The location depends on where the limit falls
+                assert!(matches!(
+                    messages.as_slice(),
+                    [Err(ParseError { kind: ParseErrorKind::LineTooLong, position, .. })]
+                        if *position == max_line_length + 1
+                ));
+            } else {
+                assert_eq!(messages.as_slice(), &[Ok(message.clone())]);
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(3)]
+    #[case(usize::MAX)]
+    fn test_raw_lines(#[case] chunk_size: usize) {
+        let options = ParserOptions {
+            capture_raw_lines: true,
+            ..Default::default()
+        };
+        let mut parser = Parser::with_options(20, options);
+        let data = b"\n \n?first  \\_x\r\n?bad\0\n!second\t\n?long-line-is-too-long\n #third[1]\n";
+        let mut raw_lines = vec![];
+        for chunk in data.chunks(chunk_size) {
+            for result in parser.append(chunk).with_raw_lines() {
+                raw_lines.push(match result {
+                    Ok((_, raw)) => Ok(raw.into_owned()),
+                    Err((error, raw)) => Err((error, raw.into_owned())),
+                });
+            }
+        }
+        assert_eq!(
+            raw_lines,
+            vec![
+                Ok(b"?first  \\_x\r".to_vec()),
+                Err((
+                    at_line(
+                        ParseError::new(ParseErrorKind::InvalidCharacter, 5, FieldLocation::Name),
+                        16
+                    ),
+                    b"?bad\0\n".to_vec()
+                )),
+                Ok(b"!second\t\n".to_vec()),
+                Err((
+                    at_line(
+                        ParseError::new(ParseErrorKind::LineTooLong, 21, FieldLocation::Name),
+                        31
+                    ),
+                    b"?long-line-is-too-lo".to_vec()
+                )),
+                Err((
+                    at_line(
+                        ParseError::new(ParseErrorKind::InvalidCharacter, 2, FieldLocation::Type),
+                        54
+                    ),
+                    b" #third[1]\n".to_vec()
+                )),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_set_max_line_length() {
+        let mut parser = Parser::new(100);
+        assert_eq!(parser.append(b"?hello world").count(), 0);
+        parser.set_max_line_length(5);
+        let messages: Vec<_> = parser.append(b" again\n?hi\n?toolong\n").collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[
+                Err(ParseError::new(
+                    ParseErrorKind::LineTooLong,
+                    13,
+                    FieldLocation::Argument(0)
+                )),
+                Ok(msg!(Request, b"hi", None)),
+                Err(at_line(
+                    ParseError::new(ParseErrorKind::LineTooLong, 6, FieldLocation::Name),
+                    23
+                )),
+            ]
+        );
+
+        // Raising the limit mid-line allows the line to continue
+        assert_eq!(parser.append(b"?hel").count(), 0);
+        parser.set_max_line_length(100);
+        let messages: Vec<_> = parser.append(b"lo world\n").collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[Ok(msg!(Request, b"hello", None, b"world"))]
+        );
+    }
+
+    #[test]
+    fn test_raw_lines_merge_crlf() {
+        let options = ParserOptions {
+            capture_raw_lines: true,
+            merge_crlf: true,
+            ..Default::default()
+        };
+        let data = b"?a b\r\n?c\r\n";
+        for split in 0..=data.len() {
+            let mut parser = Parser::with_options(100, options.clone());
+            let mut raw_lines = vec![];
+            for chunk in [&data[..split], &data[split..]] {
+                for result in parser.append(chunk).with_raw_lines() {
+                    raw_lines.push(result.unwrap().1.into_owned());
+                }
+            }
+            assert_eq!(
+                raw_lines,
+                vec![b"?a b\r".to_vec(), b"?c\r".to_vec()],
+                "split at {split}"
+            );
+        }
+    }
+
+    /// Skipped and blank lines must not leave bytes behind for the next raw line
+    #[test]
+    fn test_raw_lines_skipped() {
+        let options = ParserOptions {
+            capture_raw_lines: true,
+            ..Default::default()
+        };
+        let data = b"#skipped x\n   \n?kept\n";
+        let mut types = MessageTypeSet::empty();
+        types.insert(MessageType::Request);
+        for split in 0..=data.len() {
+            let mut parser = Parser::with_options(100, options.clone());
+            let mut raw_lines = vec![];
+            for chunk in [&data[..split], &data[split..]] {
+                for result in parser.append_filtered(chunk, types).with_raw_lines() {
+                    raw_lines.push(result.unwrap().1.into_owned());
+                }
+            }
+            assert_eq!(raw_lines, vec![b"?kept\n".to_vec()], "split at {split}");
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "capture_raw_lines")]
+    fn test_raw_lines_disabled() {
+        let mut parser = Parser::new(100);
+        parser.append(b"?foo\n").with_raw_lines();
+    }
+
+    #[rstest]
+    fn test_append_shared(mut parser: Parser) {
+        parser.append(b"?fir").for_each(drop);
+        let data = b"st a\n!second[1] plain esc\\_aped \\@ x\n?bad\0\n?thi";
+        let range = data.as_ptr_range();
+        let is_shared = |field: &SharedField<&[u8]>| match field {
+            SharedField::Shared(slice) => {
+                assert!(range.contains(&slice.as_ptr()));
+                true
+            }
+            SharedField::Owned(_) => false,
+        };
+        let messages: Vec<_> = parser
+            .append_shared(data, |field| field)
+            .map(|result| {
+                result.map(|msg| {
+                    let shared: Vec<bool> = msg.arguments.iter().map(is_shared).collect();
+                    (msg.clone().into_owned(), is_shared(&msg.name), shared)
+                })
+            })
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                Ok((
+                    ParsedMessage::into_owned(msg!(Request, b"first", None, b"a")),
+                    false,
+                    vec![true]
+                )),
+                Ok((
+                    ParsedMessage::into_owned(msg!(
+                        Reply,
+                        b"second",
+                        Some(1),
+                        b"plain",
+                        b"esc aped",
+                        b"",
+                        b"x"
+                    )),
+                    true,
+                    vec![true, false, false, true]
+                )),
+                Err(at_line(
+                    ParseError::new(ParseErrorKind::InvalidCharacter, 5, FieldLocation::Name),
+                    41
+                )),
+            ]
+        );
+        let messages: Vec<_> = parser.append(b"rd\n").collect();
+        assert_eq!(messages.as_slice(), &[Ok(msg!(Request, b"third", None))]);
+    }
+
+    #[rstest]
+    fn test_append_vectored(mut parser: Parser) {
+        let slices = [
+            IoSlice::new(b"?hel"),
+            IoSlice::new(b""),
+            IoSlice::new(b"lo wor"),
+            IoSlice::new(b"ld\n!reply ar"),
+            IoSlice::new(b"g\n#partial"),
+        ];
+        let messages: Vec<_> = parser.append_vectored(&slices).collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[
+                Ok(msg!(Request, b"hello", None, b"world")),
+                Ok(msg!(Reply, b"reply", None, b"arg")),
+            ]
+        );
+        // The partial message must be retained for the next call
+        let slices = [IoSlice::new(b" x\n")];
+        let messages: Vec<_> = parser.append_vectored(&slices).collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[Ok(msg!(Inform, b"partial", None, b"x"))]
+        );
+        assert_eq!(parser.append_vectored(&[]).count(), 0);
+    }
+
+    #[rstest]
+    #[case(b"#foo[1]\n", false, false)]
+    #[case(b"#foo[1]\n", true, true)]
+    #[case(b"#foo:1\n", true, true)]
+    #[case(b"#foo\n", true, false)]
+    #[case(b"?foo[1]\n", true, false)]
+    #[case(b"!foo[1]\n", true, false)]
+    #[case(b"?foo:1\n", true, false)]
+    fn test_forbid_inform_id(#[case] input: &[u8], #[case] forbid: bool, #[case] fail: bool) {
+        let options = ParserOptions {
+            allow_colon_id: true,
+            forbid_inform_id: forbid,
+            ..Default::default()
+        };
+        let mut parser = Parser::with_options(100, options);
+        let messages: Vec<_> = parser.append(input).collect();
+        if fail {
+            assert_eq!(
+                messages.as_slice(),
+                &[Err(ParseError::new(
+                    ParseErrorKind::InformIdNotAllowed,
+                    5,
+                    FieldLocation::Id
+                ))]
+            );
+        } else {
+            assert!(matches!(messages.as_slice(), &[Ok(_)]));
+        }
+    }
+
+    #[rstest]
+    #[case(b"?foo\n")]
+    #[case(b"\n\r \t\n!foo[123]\t\\_\\@ \\\\x   \r")]
+    fn test_wire_length(#[case] input: &[u8]) {
+        let line_start = input.iter().position(|c| b"?!#".contains(c)).unwrap();
+        let wire_length = input.len() - line_start;
+        let mut parser = Parser::new(wire_length);
+        let infos: Vec<_> = parser
+            .append(input)
+            .with_info()
+            .map(|result| result.map(|(_, info)| info.wire_length))
+            .collect();
+        assert_eq!(infos, vec![Ok(wire_length)]);
+
+        // Any lower limit must reject the message
+        let mut parser = Parser::new(wire_length - 1);
+        let messages: Vec<_> = parser.append(input).collect();
+        assert!(matches!(messages.as_slice(), &[Err(_)]));
+    }
+
+    #[test]
+    fn test_name_interner() {
+        let names: HashMap<&[u8], u32> = [(b"help".as_slice(), 1), (b"watchdog".as_slice(), 2)]
+            .into_iter()
+            .collect();
+        let options = ParserOptions {
+            name_interner: Some(Arc::new(names)),
+            ..Default::default()
+        };
+        let mut parser = Parser::with_options(100, options);
+        let mut symbols = vec![];
+        for chunk in [b"?he".as_slice(), b"lp\n!watch", b"dog[1] ok\n?unknown\n"] {
+            for result in parser.append(chunk).with_info() {
+                let (msg, info) = result.unwrap();
+                symbols.push((msg.name.into_owned(), info.name_symbol));
+            }
+        }
+        assert_eq!(
+            symbols,
+            vec![
+                (b"help".to_vec(), Some(1)),
+                (b"watchdog".to_vec(), Some(2)),
+                (b"unknown".to_vec(), None),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(b"?foo \\\\\\\\\\\\ \\_\\_\\_\n", Ok(()))]
+    #[case(b"?foo \\\\\\\\\\\\\\\\\n", Err(12))]
+    #[case(b"?foo \\\\x\\_\\\\\\_\n", Err(13))]
+    fn test_max_escapes(#[case] input: &[u8], #[case] expected: Result<(), usize>) {
+        let options = ParserOptions {
+            max_escapes_per_argument: Some(3),
+            ..Default::default()
+        };
+        for split in 0..input.len() {
+            let mut parser = Parser::with_options(100, options.clone());
+            let mut results: Vec<_> = parser.append(&input[..split]).collect();
+            results.extend(parser.append(&input[split..]));
+            let results: Vec<_> = results
+                .into_iter()
+                .map(|result| result.map(|_| ()).map_err(|err| err.position))
+                .collect();
+            assert_eq!(results, vec![expected]);
+        }
+    }
+
+    #[rstest]
+    #[case(b"?foo a b \\@\n", Ok(()))]
+    #[case(b"?foo a b   \n", Ok(()))]
+    #[case(b"?foo a b \\@ d\n", Err((13, FieldLocation::Argument(3))))]
+    #[case(b"?foo \\@ \\@ \\@ \\@ \\@\n", Err((15, FieldLocation::Argument(3))))]
+    fn test_max_arguments(
+        #[case] input: &[u8],
+        #[case] expected: Result<(), (usize, FieldLocation)>,
+    ) {
+        let options = ParserOptions {
+            max_arguments: Some(3),
+            ..Default::default()
+        };
+        for split in 0..input.len() {
+            let mut parser = Parser::with_options(100, options.clone());
+            let mut results: Vec<_> = parser.append(&input[..split]).collect();
+            results.extend(parser.append(&input[split..]));
+            let results: Vec<_> = results
+                .into_iter()
+                .map(|result| {
+                    result
+                        .map(|_| ())
+                        .map_err(|err| (err.position, err.field_location()))
+                })
+                .collect();
+            assert_eq!(results, vec![expected]);
+        }
+    }
+
+    fn partial(
+        mtype: MessageType,
+        name: Option<&[u8]>,
+        mid: Option<u32>,
+        arguments: &[&[u8]],
+    ) -> Option<PartialMessage> {
+        Some(PartialMessage {
+            mtype,
+            name: name.map(|name| name.to_vec()),
+            mid,
+            arguments: arguments.iter().map(|arg| arg.to_vec()).collect(),
+        })
+    }
+
+    #[rstest]
+    #[case(b"?foo\0\n", partial(Request, None, None, &[]))]
+    #[case(b"!foo[12\0\n", partial(Reply, Some(b"foo"), None, &[]))]
+    #[case(b"#foo[12] a \\@ b\0c\n", partial(Inform, Some(b"foo"), Some(12), &[b"a", b""]))]
+    #[case(b"?foo a \\q\n", partial(Request, Some(b"foo"), None, &[b"a"]))]
+    #[case(b"x?foo\n", None)]
+    fn test_keep_partial(#[case] input: &[u8], #[case] expected: Option<PartialMessage>) {
+        let options = ParserOptions {
+            keep_partial: true,
+            ..Default::default()
+        };
+        for split in 0..input.len() {
+            let mut parser = Parser::with_options(100, options.clone());
+            let mut results: Vec<_> = parser.append(&input[..split]).collect();
+            results.extend(parser.append(&input[split..]));
+            assert_eq!(results.len(), 1);
+            let error = results[0].as_ref().unwrap_err();
+            assert_eq!(error.partial(), expected.as_ref());
+        }
+        // Without the option, nothing is kept
+        let mut parser = Parser::new(100);
+        let results: Vec<_> = parser.append(input).collect();
+        assert_eq!(results[0].as_ref().unwrap_err().partial(), None);
+    }
+
+    #[rstest]
+    #[case(b"", Ok(b"".to_vec()))]
+    #[case(b"\\@", Ok(b"".to_vec()))]
+    #[case(b"a\\_b\\\\\\0\\n\\r\\e\\t", Ok(b"a b\\\0\n\r\x1b\t".to_vec()))]
+    #[case(b"\xff[]", Ok(b"\xff[]".to_vec()))]
+    #[case(
+        b"a b",
+        Err(ParseError::new(ParseErrorKind::InvalidCharacter, 2, FieldLocation::Argument(0)))
+    )]
+    #[case(
+        b"ab\n",
+        Err(ParseError::new(ParseErrorKind::InvalidCharacter, 3, FieldLocation::Argument(0)))
+    )]
+    #[case(
+        b"a\\x",
+        Err(ParseError::new(ParseErrorKind::BadEscape, 3, FieldLocation::Argument(0)))
+    )]
+    #[case(
+        b"a\0",
+        Err(ParseError::new(ParseErrorKind::InvalidCharacter, 2, FieldLocation::Argument(0)))
+    )]
+    #[case(
+        b"ab\\",
+        Err(ParseError::new(ParseErrorKind::UnterminatedEscape, 3, FieldLocation::Argument(0)))
+    )]
+    fn test_unescape_argument(#[case] input: &[u8], #[case] expected: Result<Vec<u8>, ParseError>) {
+        assert_eq!(unescape_argument(input), expected);
+    }
+
+    #[test]
+    fn test_unescape_argument_bad_escape_display() {
+        let error = unescape_argument(b"a\\x").unwrap_err();
+        assert_eq!(error.kind(), ParseErrorKind::BadEscape);
+        assert_eq!(error.to_string(), "\"Invalid character\" at character 3");
+    }
+
+    #[rstest]
+    #[case(b"", None)]
+    #[case(b"?foo\n", None)]
+    #[case(b"  ", None)]
+    #[case(b"?foo a", Some(Ok(msg!(MessageType::Request, b"foo", None, b"a"))))]
+    #[case(b"!foo[5]", Some(Ok(msg!(MessageType::Reply, b"foo", Some(5)))))]
+    #[case(
+        b"?x a\\",
+        Some(Err(ParseError::new(
+            ParseErrorKind::UnterminatedEscape,
+            5,
+            FieldLocation::Argument(0)
+        )))
+    )]
+    #[case(
+        b"?x\\",
+        Some(Err(ParseError::new(ParseErrorKind::InvalidCharacter, 3, FieldLocation::Name)))
+    )]
+    #[case(
+        b"?foo[",
+        Some(Err(ParseError::new(ParseErrorKind::UnexpectedEnd, 6, FieldLocation::Id)))
+    )]
+    #[case(
+        b"!",
+        Some(Err(ParseError::new(ParseErrorKind::UnexpectedEnd, 2, FieldLocation::Name)))
+    )]
+    fn test_finish(
+        mut parser: Parser,
+        #[case] input: &[u8],
+        #[case] expected: Option<Result<ParsedMessage<'static>, ParseError>>,
+    ) {
+        assert!(parser.append(input).all(|result| result.is_ok()));
+        let lines_seen = parser.lines_seen();
+        assert_eq!(parser.finish(), expected);
+        let terminated = u64::from(!input.is_empty() && !input.ends_with(b"\n"));
+        assert_eq!(parser.lines_seen(), lines_seen + terminated);
+        assert_eq!(parser.buffer_size(), 0);
+        // The parser must be back in the initial state
+        assert_eq!(parser.finish(), None);
+        let messages: Vec<_> = parser.append(b"?bar\n").collect();
+        assert_eq!(messages, vec![Ok(msg!(MessageType::Request, b"bar", None))]);
+    }
+
+    #[rstest]
+    #[case(Separators::Tab, b"?foo\ta b\tc\n", Some(vec!["a b", "c"]))]
+    #[case(Separators::Tab, b"?foo[1]\t\t a \t\\_\n", Some(vec![" a ", " "]))]
+    #[case(Separators::Tab, b" \t\n?foo\t \n", Some(vec![" "]))]
+    #[case(Separators::Tab, b"?foo a\n", None)]
+    #[case(Separators::Tab, b"?foo[1] a\n", None)]
+    #[case(Separators::Space, b"?foo a\tb  \tc\n", Some(vec!["a\tb", "\tc"]))]
+    #[case(Separators::Space, b"?foo\ta\n", None)]
+    #[case(Separators::SpaceOrTab, b"?foo a\tb\n", Some(vec!["a", "b"]))]
+    fn test_separator_policy(
+        #[case] separators: Separators,
+        #[case] input: &[u8],
+        #[case] arguments: Option<Vec<&str>>,
+    ) {
+        let options = ParserOptions {
+            separators,
+            ..Default::default()
+        };
+        for split in 0..=input.len() {
+            let mut parser = Parser::with_options(100, options.clone());
+            let mut messages: Vec<_> = parser.append(&input[..split]).collect();
+            messages.extend(parser.append(&input[split..]));
+            assert_eq!(messages.len(), 1, "split at {split}");
+            match &arguments {
+                Some(arguments) => {
+                    let message = messages[0].as_ref().unwrap();
+                    let arguments: Vec<&[u8]> =
+                        arguments.iter().map(|arg| arg.as_bytes()).collect();
+                    assert_eq!(message.arguments, arguments, "split at {split}");
+                }
+                None => assert!(messages[0].is_err(), "split at {split}"),
+            }
+        }
+    }
+
+    #[rstest]
+    #[case(b"?foo\n", false, false)]
+    #[case(b"?foo a b\n", false, false)]
+    #[case(b"?foo a b \r", false, false)]
+    #[case(b"?foo\ta b\n", true, false)]
+    #[case(b"?foo[1]\ta\n", true, false)]
+    #[case(b"?foo a\tb\n", true, false)]
+    #[case(b"?foo  a b\n", false, true)]
+    #[case(b"?foo[1]  a\n", false, true)]
+    #[case(b"?foo a b  \n", false, true)]
+    #[case(b"?foo a \tb\n", true, true)]
+    #[case(b"?foo a  \t b\n", true, true)]
+    fn test_separators(#[case] input: &[u8], #[case] tab: bool, #[case] multiple: bool) {
+        for split in 0..input.len() {
+            let mut parser = Parser::new(100);
+            let mut infos: Vec<_> = parser.append(&input[..split]).with_info().collect();
+            infos.extend(parser.append(&input[split..]).with_info());
+            assert_eq!(infos.len(), 1);
+            let info = infos.pop().unwrap().unwrap().1;
+            assert_eq!(info.had_tab_separator, tab);
+            assert_eq!(info.had_multiple_separators, multiple);
+        }
+    }
+
+    #[rstest]
+    fn test_at_line_boundary(mut parser: Parser) {
+        assert!(parser.at_line_boundary());
+        for (data, boundary) in [
+            (b"?help".as_slice(), false),
+            (b" x\n", true),
+            (b" ", false),
+            (b"\n", true),
+            (b"?bad\0", false),
+            (b"\n#ok\r", true),
+        ] {
+            parser.append(data).for_each(drop);
+            assert_eq!(parser.at_line_boundary(), boundary);
+        }
+    }
+
+    #[test]
+    fn test_current_state() {
+        let mut parser = Parser::new(100);
+        assert_eq!(parser.current_state(), ParserPhase::Idle);
+        let steps: &[(&[u8], ParserPhase)] = &[
+            (b" ", ParserPhase::BlankLine),
+            (b"\n", ParserPhase::Idle),
+            (b"?na", ParserPhase::Name),
+            (b"me[1", ParserPhase::Id),
+            (b"] ", ParserPhase::BeforeArgument),
+            (b"ar", ParserPhase::Argument),
+            (b"\\", ParserPhase::Escape),
+            (b"n", ParserPhase::Argument),
+            (b"\0", ParserPhase::Error),
+            (b"\n", ParserPhase::Idle),
+        ];
+        for (data, phase) in steps {
+            parser.append(data).for_each(drop);
+            assert_eq!(parser.current_state(), *phase);
+        }
+        parser
+            .append_filtered(b"#skipped", MessageTypeSet::empty())
+            .for_each(drop);
+        assert_eq!(parser.current_state(), ParserPhase::Skip);
+    }
+
+    #[test]
+    fn test_blank_lines() {
+        let input = b"\n?a\r\n  \n#b\n\n";
+        let expected = [
+            ParseEvent::BlankLine,
+            ParseEvent::Message(msg!(Request, b"a", None)),
+            ParseEvent::BlankLine,
+            ParseEvent::BlankLine,
+            ParseEvent::Message(msg!(Inform, b"b", None)),
+            ParseEvent::BlankLine,
+        ];
+        for split in 0..=input.len() {
+            let mut parser = Parser::new(100);
+            let mut events: Vec<_> = parser.append(&input[..split]).with_blank_lines().collect();
+            events.extend(parser.append(&input[split..]).with_blank_lines());
+            let events: Vec<_> = events.into_iter().map(Result::unwrap).collect();
+            assert_eq!(events, expected, "split at {split}");
+        }
+        // Without the adaptor, blank lines are still skipped
+        let mut parser = Parser::new(100);
+        assert_eq!(parser.append(input).count(), 2);
+    }
+
+    #[rstest]
+    #[case(b"?help[1] a\\_b\n", Ok(msg!(Request, b"help", Some(1), b"a b")))]
+    #[case(b"#log\r\n", Ok(msg!(Inform, b"log", None)))]
+    #[case(b"!ok\r", Ok(msg!(Reply, b"ok", None)))]
+    #[case(b"?help a", Err((ParseErrorKind::UnexpectedEnd, 8, 7)))]
+    #[case(b"", Err((ParseErrorKind::UnexpectedEnd, 1, 0)))]
+    #[case(b"?help\n\n", Err((ParseErrorKind::TrailingData, 1, 6)))]
+    #[case(b"?help\n?help\n", Err((ParseErrorKind::TrailingData, 1, 6)))]
+    #[case(b"?help\r\n\n", Err((ParseErrorKind::TrailingData, 1, 7)))]
+    #[case(b"?he\0lp\n?help\n", Err((ParseErrorKind::InvalidCharacter, 4, 3)))]
+    fn test_from_bytes(
+        #[case] data: &[u8],
+        #[case] expected: Result<ParsedMessage<'static>, (ParseErrorKind, usize, u64)>,
+    ) {
+        let result = Message::from_bytes(data);
+        match expected {
+            Ok(message) => assert_eq!(result, Ok(message)),
+            Err((kind, position, stream_position)) => {
+                let error = result.unwrap_err();
+                assert_eq!(error.kind(), kind);
+                assert_eq!(error.position, position);
+                assert_eq!(error.stream_position(), stream_position);
+            }
+        }
+    }
+
+    /// Reader that returns a few bytes at a time, with interruptions
+    struct SlowReader<'a> {
+        data: &'a [u8],
+        interrupt: bool,
+    }
+
+    impl Read for SlowReader<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.interrupt = !self.interrupt;
+            if self.interrupt {
+                return Err(io::ErrorKind::Interrupted.into());
+            }
+            let n = self.data.len().min(buf.len()).min(3);
+            buf[..n].copy_from_slice(&self.data[..n]);
+            self.data = &self.data[n..];
+            if self.data.is_empty() && n == 0 {
+                Err(io::ErrorKind::BrokenPipe.into())
+            } else {
+                Ok(n)
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_messages() {
+        let data = b"?first a\n!bad\0\n#last b";
+        let results: Vec<_> = read_messages(data.as_slice(), 100)
+            .map(|result| result.map_err(|err| matches!(err, ReadError::Parse(_))))
+            .collect();
+        let first: ParsedMessage = msg!(Request, b"first", None, b"a");
+        let first = first.into_owned();
+        let last: ParsedMessage = msg!(Inform, b"last", None, b"b");
+        let last = last.into_owned();
+        assert_eq!(
+            results,
+            vec![Ok(first.clone()), Err(true), Ok(last.clone())]
+        );
+
+        // The slow reader fails instead of reporting the end of the input
+        let reader = SlowReader {
+            data: b"?first a\n#last b\n",
+            interrupt: false,
+        };
+        let results: Vec<_> = read_messages(reader, 100)
+            .map(|result| result.map_err(|err| matches!(err, ReadError::Io(_))))
+            .collect();
+        assert_eq!(results, vec![Ok(first), Ok(last), Err(true)]);
+    }
+
+    #[rstest]
+    fn test_parse_all(mut parser: Parser) {
+        assert_eq!(parser.parse_all(b"?first a\n?bad\0\n#sec").len(), 2);
+        assert_eq!(
+            parser.parse_all(b"ond\n"),
+            vec![Ok(msg!(Inform, b"second", None))]
+        );
+    }
+
+    #[test]
+    fn test_max_name_length() {
+        let options = ParserOptions {
+            max_name_length: Some(5),
+            ..Default::default()
+        };
+        let input = b"?abcde x\n#abcdef x\n!ab\n";
+        for split in 0..=input.len() {
+            let mut parser = Parser::with_options(100, options.clone());
+            let mut messages: Vec<_> = parser.append(&input[..split]).collect();
+            messages.extend(parser.append(&input[split..]));
+            assert_eq!(
+                messages,
+                vec![
+                    Ok(msg!(Request, b"abcde", None, b"x")),
+                    Err(at_line(
+                        ParseError::new(ParseErrorKind::NameTooLong, 7, FieldLocation::Name),
+                        9
+                    )),
+                    Ok(msg!(Reply, b"ab", None)),
+                ],
+                "split at {split}"
+            );
+        }
+    }
+
+    #[cfg(feature = "stats")]
+    #[test]
+    fn test_stats() {
+        let input = b"?foo abcdefgh \\\\\\\\\\\\\n";
+        let mut parser = Parser::new(100);
+        assert_eq!(parser.append(input).count(), 1);
+        let stats = parser.stats();
+        assert_eq!(
+            stats.transitions + stats.fast_path_bytes + stats.escape_run_bytes,
+            input.len() as u64
+        );
+        assert_eq!(stats.fast_path_runs, 2);
+        assert_eq!(stats.fast_path_bytes, 9);
+        assert_eq!(stats.escape_run_bytes, 4);
+        parser.reset_counters();
+        assert_eq!(parser.stats(), ParserStats::default());
+    }
+
+    /// Check that the length accounting does not overflow for a line of
+    /// `usize::MAX` bytes. Such a line can't actually be supplied, so the
+    /// final part of it is faked by setting the line length directly.
+    #[rstest]
+    #[case(b"bc d\n", Err(ParseErrorKind::LineTooLong))]
+    #[case(b"\n", Err(ParseErrorKind::LineTooLong))]
+    #[case(b"", Ok(()))]
+    fn test_line_length_overflow(
+        #[case] input: &[u8],
+        #[case] expected: Result<(), ParseErrorKind>,
+    ) {
+        let mut parser = Parser::new(usize::MAX);
+        assert_eq!(parser.append(b"?foo a").count(), 0);
+        parser.line_length = usize::MAX;
+        let kinds: Vec<_> = parser
+            .append(input)
+            .map(|result| result.map(|_| ()).map_err(|err| err.kind))
+            .collect();
+        match expected {
+            Ok(()) => assert!(kinds.is_empty()),
+            Err(kind) => assert_eq!(kinds, [Err(kind)]),
+        }
+        assert_eq!(
+            parser
+                .finish()
+                .map(|result| result.map(|_| ()).map_err(|err| err.kind)),
+            match expected {
+                Ok(()) => Some(Err(ParseErrorKind::LineTooLong)),
+                Err(_) => None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_errors_not_fatal() {
+        let input = b"?a\n?b\0 x\n?c[0]\n\x01\n?d\n";
+        for split in 0..=input.len() {
+            let mut parser = Parser::new(100);
+            let mut results: Vec<_> = parser.append(&input[..split]).collect();
+            results.extend(parser.append(&input[split..]));
+            let results: Vec<_> = results.into_iter().map(|result| result.is_ok()).collect();
+            assert_eq!(
+                results,
+                [true, false, false, false, true],
+                "split at {split}"
+            );
+        }
+    }
+
+    #[rstest]
+    fn test_append_filtered(mut parser: Parser) {
+        let input = b"?req a b\n#inf 1\n!rep[1] ok\n#inf\\x 2\n!rep\0\n#bad\0\n#inf 3\n";
+        let informs = MessageTypeSet::from(MessageType::Inform);
+        for split in 0..input.len() {
+            let base = parser.stream_offset();
+            let mut results: Vec<_> = parser.append_filtered(&input[..split], informs).collect();
+            results.extend(parser.append_filtered(&input[split..], informs));
+            assert_eq!(
+                results,
+                vec![
+                    Ok(msg!(Inform, b"inf", None, b"1")),
+                    Err(at_line(
+                        ParseError::new(ParseErrorKind::InvalidCharacter, 5, FieldLocation::Name),
+                        base + 27
+                    )),
+                    Err(at_line(
+                        ParseError::new(ParseErrorKind::InvalidCharacter, 5, FieldLocation::Name),
+                        base + 42
+                    )),
+                    Ok(msg!(Inform, b"inf", None, b"3")),
+                ]
+            );
+        }
+        assert_eq!(parser.buffer_size(), 0);
+    }
+
+    #[test]
+    fn test_append_filtered_long() {
+        // Skipped lines are not subject to the line length limit
+        let mut parser = Parser::new(10);
+        let informs = [MessageType::Inform].into_iter().collect();
+        let results: Vec<_> = parser
+            .append_filtered(b"?a-very-long-request\n#inf\n", informs)
+            .collect();
+        assert_eq!(results, vec![Ok(msg!(Inform, b"inf", None))]);
+    }
+
+    #[rstest]
+    #[case(b"x\n", FieldLocation::Type)]
+    #[case(b"  x\n", FieldLocation::Type)]
+    #[case(b"?\n", FieldLocation::Name)]
+    #[case(b"?1foo\n", FieldLocation::Name)]
+    #[case(b"?foo_bar\n", FieldLocation::Name)]
+    #[case(b"?foo[\n", FieldLocation::Id)]
+    #[case(b"?foo[0]\n", FieldLocation::Id)]
+    #[case(b"?foo[1x]\n", FieldLocation::Id)]
+    #[case(b"?foo[1]x\n", FieldLocation::Id)]
+    #[case(b"?foo[99999999999]\n", FieldLocation::Id)]
+    #[case(b"?foo \0\n", FieldLocation::Argument(0))]
+    #[case(b"?foo a\0\n", FieldLocation::Argument(0))]
+    #[case(b"?foo a b  \0\n", FieldLocation::Argument(2))]
+    #[case(b"?foo a b \\x c\n", FieldLocation::Argument(2))]
+    #[case(b"?foo a b c\\\n", FieldLocation::Argument(2))]
+    #[case(b"?foo a b c\x1b\n", FieldLocation::Argument(2))]
+    fn test_field_location(#[case] input: &[u8], #[case] location: FieldLocation) {
+        for split in 0..input.len() {
+            let mut parser = Parser::new(100);
+            let mut results: Vec<_> = parser.append(&input[..split]).collect();
+            results.extend(parser.append(&input[split..]));
+            let locations: Vec<_> = results
+                .into_iter()
+                .map(|result| result.unwrap_err().field_location())
+                .collect();
+            assert_eq!(locations, vec![location]);
+        }
+    }
+
+    #[test]
+    fn test_message_time() {
+        let options = ParserOptions {
+            on_message_time: Some(Instant::now),
+            ..Default::default()
+        };
+        let mut parser = Parser::with_options(100, options);
+        let before = Instant::now();
+        let times: Vec<_> = parser
+            .append(b"?foo\n?bar\n")
+            .with_info()
+            .map(|result| result.unwrap().1.completed_at.unwrap())
+            .collect();
+        let after = Instant::now();
+        assert_eq!(times.len(), 2);
+        assert!(before <= times[0] && times[0] <= times[1] && times[1] <= after);
+
+        // Without a clock, no time is reported
+        let mut parser = Parser::new(100);
+        let info = parser
+            .append(b"?foo\n")
+            .with_info()
+            .next()
+            .unwrap()
+            .unwrap()
+            .1;
+        assert_eq!(info.completed_at, None);
+    }
+
+    #[rstest]
+    fn test_coalesce(mut parser: Parser) {
+        let input = b"#s 1\n#s 1\n#s 1\n?x\n#s 1\n?1\n#s 1\n#s 1\n#s 2\n";
+        let results: Vec<_> = parser
+            .append(input)
+            .coalesce()
+            .map(|result| result.map_err(|_| ()))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Ok((msg!(MessageType::Inform, b"s", None, b"1"), 3)),
+                Ok((msg!(MessageType::Request, b"x", None), 1)),
+                Ok((msg!(MessageType::Inform, b"s", None, b"1"), 1)),
+                Err(()),
+                Ok((msg!(MessageType::Inform, b"s", None, b"1"), 2)),
+                Ok((msg!(MessageType::Inform, b"s", None, b"2"), 1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_too_long() {
+        let mut parser = Parser::new(10);
+        let messages: Vec<_> = parser.append(&b"?hello1234\n").collect();
+        assert_eq!(
+            messages.as_slice(),
+            &[Err(ParseError::new(
+                ParseErrorKind::LineTooLong,
+                11,
+                FieldLocation::Name
+            ))]
+        );
+        let messages: Vec<_> = parser.append(&b"?hello123\n").collect();
+        assert_eq!(messages.as_slice(), &[Ok(msg!(Request, b"hello123", None))]);
+    }
+
+    /// Feed a long invalid line, split into pieces of `chunk_size` bytes.
+    ///
+    /// Returns the errors produced and checks that the garbage was not
+    /// retained by the parser.
+    fn feed_garbage(chunk_size: usize) -> Vec<Result<(), ParseError>> {
+        let mut data = b"?garbage arg1 arg2\0".to_vec();
+        data.extend_from_slice(&[b'x'; 100000]);
+        let mut parser = Parser::new(1000);
+        for chunk in data.chunks(chunk_size) {
+            assert_eq!(parser.append(chunk).count(), 0);
+        }
+        assert_eq!(parser.state, State::Error);
+        assert_eq!(parser.buffer_size(), 1000);
+        assert!(parser.name.is_empty());
+        assert!(parser.arguments.is_empty());
+        parser.append(b"\n").map(|msg| msg.map(|_| ())).collect()
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(7)]
+    #[case(999)]
+    #[case(1000)]
+    #[case(4096)]
+    fn test_long_garbage(#[case] chunk_size: usize) {
+        let messages = feed_garbage(chunk_size);
+        assert_eq!(messages, feed_garbage(usize::MAX));
+        assert_eq!(
+            messages.as_slice(),
+            &[Err(ParseError::new(
+                ParseErrorKind::InvalidCharacter,
+                19,
+                FieldLocation::Argument(1)
+            ))]
+        );
+    }
+
+    #[rstest]
+    #[case(1)]
+    #[case(8)]
+    #[case(usize::MAX)]
+    fn test_mid_overflow_split(#[case] chunk_size: usize, mut parser: Parser) {
+        let data = b"?a[21474836470]\n";
+        let mut messages = vec![];
+        for chunk in data.chunks(chunk_size) {
+            messages.extend(parser.append(chunk));
+        }
+        assert_eq!(
+            messages.as_slice(),
+            &[Err(ParseError::new(
+                ParseErrorKind::MessageIdOverflow,
+                14,
+                FieldLocation::Id
+            ))]
+        );
+    }
+
+    #[rstest]
+    #[case(OnMidOverflow::Clamp, b"?a[21474836470] x\n", Some(MAX_MESSAGE_ID))]
+    #[case(OnMidOverflow::Drop, b"?a[21474836470] x\n", None)]
+    #[case(
+        OnMidOverflow::Clamp,
+        b"?a:99999999999999999999 x\n",
+        Some(MAX_MESSAGE_ID)
+    )]
+    #[case(OnMidOverflow::Drop, b"?a:99999999999999999999 x\n", None)]
+    fn test_mid_overflow_policy(
+        #[case] on_mid_overflow: OnMidOverflow,
+        #[case] input: &[u8],
+        #[case] mid: Option<u32>,
+    ) {
+        let options = ParserOptions {
+            on_mid_overflow,
+            allow_colon_id: true,
+            ..Default::default()
+        };
+        for split in 0..=input.len() {
+            let mut parser = Parser::with_options(1000, options.clone());
+            let mut messages: Vec<_> = parser.append(&input[..split]).collect();
+            messages.extend(parser.append(&input[split..]));
+            assert_eq!(messages.as_slice(), &[Ok(msg!(Request, b"a", mid, b"x"))]);
+        }
+        // Non-digits in the ID are still errors
+        let mut parser = Parser::with_options(1000, options);
+        let messages: Vec<_> = parser.append(b"?a[99999999999x]\n").collect();
+        assert!(matches!(messages.as_slice(), &[Err(_)]));
+    }
+
+    #[rstest]
+    #[case(b"?a\r\r#b\n", true, 1)]
+    #[case(b"?a\r\n#b\n", true, 0)]
+    #[case(b"?a\n\r#b\n", true, 1)]
+    #[case(b"?a\r\n\r\n#b\n", true, 1)]
+    #[case(b"?a\r\n#b\n", false, 1)]
+    #[case(b"?a\r\n\r\n#b\n", false, 3)]
+    fn test_merge_crlf(#[case] input: &[u8], #[case] merge_crlf: bool, #[case] blank: usize) {
+        let options = ParserOptions {
+            merge_crlf,
+            ..Default::default()
+        };
+        let mut expected = vec![ParseEvent::Message(msg!(Request, b"a", None))];
+        for _ in 0..blank {
+            expected.push(ParseEvent::BlankLine);
+        }
+        expected.push(ParseEvent::Message(msg!(Inform, b"b", None)));
+        for split in 0..=input.len() {
+            let mut parser = Parser::with_options(100, options.clone());
+            let mut events: Vec<_> = parser.append(&input[..split]).with_blank_lines().collect();
+            events.extend(parser.append(&input[split..]).with_blank_lines());
+            let events: Vec<_> = events.into_iter().map(Result::unwrap).collect();
+            assert_eq!(events, expected, "split at {split}");
+            assert_eq!(parser.lines_seen(), 2 + blank as u64);
+            assert_eq!(parser.stream_offset(), input.len() as u64);
+            assert!(parser.at_line_boundary());
+        }
+    }
+
+    fn split_points_strategy(size: usize) -> impl Strategy<Value = Vec<usize>> {
+        prop::collection::vec(1..(size - 1), 1..10).prop_map(move |mut x| {
+            x.push(0);
+            x.push(size);
+            x.sort();
+            x
+        })
+    }
+
+    /// Strategy that produces a message and some points at which to cut it.
+    fn split_message_strategy() -> impl Strategy<Value = (String, Vec<usize>)> {
+        text_message_strategy().prop_flat_map(|x| {
+            let len = x.len();
+            (Just(x), split_points_strategy(len))
+        })
+    }
+
+    proptest! {
+        /// Test that a variety of valid messages parse successfully
+        #[test]
+        fn success(input in text_message_strategy()) {
+            let mut parser = Parser::new(usize::MAX);
+            let messages: Vec<_> = parser.append(input.as_bytes()).collect();
+            assert!(matches!(messages.as_slice(), &[Ok(_)]));
+        }
+
+        /// Test that splitting a message doesn't change how it is parsed
+        #[test]
         fn parse_split(input in split_message_strategy(), max_line_length in 1..1000usize) {
             let (data, splits) = &input;
             let data = data.as_bytes();
@@ -519,6 +3517,18 @@ mod test {
             }
 
             assert_eq!(messages1, messages2);
+
+            let slices: Vec<_> = splits
+                .windows(2)
+                .map(|w| IoSlice::new(&data[w[0]..w[1]]))
+                .collect();
+            let mut parser3 = Parser::new(max_line_length);
+            let messages3: Vec<_> = parser3.append_vectored(&slices).collect();
+            assert_eq!(messages1, messages3);
+
+            for parser in [&parser1, &parser2, &parser3] {
+                assert_eq!(parser.stream_offset(), data.len() as u64);
+            }
         }
     }
 }
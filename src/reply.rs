@@ -0,0 +1,218 @@
+/* Copyright (c) 2024, National Research Foundation (SARAO)
+ *
+ * Licensed under the BSD 3-Clause License (the "License"); you may not use
+ * this file except in compliance with the License. You may obtain a copy
+ * of the License at
+ *
+ *   https://opensource.org/licenses/BSD-3-Clause
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Status codes of replies
+
+use crate::message::{validate_name, Message, MessageType, NameError};
+use crate::types::{ArgError, TypeError};
+
+/// Status given by the first argument of a reply.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ReplyStatus {
+    /// The request succeeded
+    Ok,
+    /// The request was valid but could not be completed
+    Fail,
+    /// The request was malformed or unknown
+    Invalid,
+}
+
+impl ReplyStatus {
+    /// The status word used on the wire.
+    pub fn as_bytes(&self) -> &'static [u8] {
+        match self {
+            ReplyStatus::Ok => b"ok",
+            ReplyStatus::Fail => b"fail",
+            ReplyStatus::Invalid => b"invalid",
+        }
+    }
+}
+
+/// Decode a status word.
+///
+/// Status words are case-sensitive, and anything other than `ok`, `fail` or
+/// `invalid` gives [TypeError::UnknownStatus].
+pub fn parse_reply_status(arg: &[u8]) -> Result<ReplyStatus, TypeError> {
+    match arg {
+        b"ok" => Ok(ReplyStatus::Ok),
+        b"fail" => Ok(ReplyStatus::Fail),
+        b"invalid" => Ok(ReplyStatus::Invalid),
+        _ => Err(TypeError::UnknownStatus),
+    }
+}
+
+impl<N, A> Message<N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    /// Decode the status of a reply.
+    ///
+    /// Returns [None] if the message is not a reply.
+    pub fn reply_status(&self) -> Option<Result<ReplyStatus, ArgError>> {
+        (self.mtype == MessageType::Reply).then(|| self.typed_arg(0, parse_reply_status))
+    }
+}
+
+impl<N, A> Message<N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]> + From<&'static [u8]>,
+{
+    /// Create a reply to a request.
+    ///
+    /// The reply has the same name and message ID as the request. The
+    /// status word is inserted before the `extra` arguments.
+    pub fn reply_to(
+        request_name: N,
+        mid: Option<u32>,
+        status: ReplyStatus,
+        extra: Vec<A>,
+    ) -> Result<Self, NameError> {
+        validate_name(request_name.as_ref())?;
+        let mut arguments = Vec::with_capacity(extra.len() + 1);
+        arguments.push(status.as_bytes().into());
+        arguments.extend(extra);
+        Ok(Self::new(MessageType::Reply, request_name, mid, arguments))
+    }
+
+    /// Create a successful reply (see [Message::reply_to]).
+    pub fn reply_ok(request_name: N, mid: Option<u32>, extra: Vec<A>) -> Result<Self, NameError> {
+        Self::reply_to(request_name, mid, ReplyStatus::Ok, extra)
+    }
+
+    /// Create a failure reply, whose second argument is `reason` (see
+    /// [Message::reply_to]).
+    pub fn reply_fail(
+        request_name: N,
+        mid: Option<u32>,
+        reason: impl Into<A>,
+    ) -> Result<Self, NameError> {
+        Self::reply_to(request_name, mid, ReplyStatus::Fail, vec![reason.into()])
+    }
+
+    /// Create an inform sent in response to a request, before the reply.
+    ///
+    /// Like a reply, it has the same name and message ID as the request.
+    pub fn reply_inform(
+        request_name: N,
+        mid: Option<u32>,
+        arguments: Vec<A>,
+    ) -> Result<Self, NameError> {
+        validate_name(request_name.as_ref())?;
+        Ok(Self::new(MessageType::Inform, request_name, mid, arguments))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rstest::*;
+
+    use crate::message::OwnedMessage;
+
+    fn message(mtype: MessageType, arguments: &[&str]) -> OwnedMessage {
+        Message::new(
+            mtype,
+            b"test".to_vec(),
+            None,
+            arguments
+                .iter()
+                .map(|arg| arg.as_bytes().to_vec())
+                .collect::<Vec<_>>(),
+        )
+    }
+
+    #[rstest]
+    #[case(ReplyStatus::Ok)]
+    #[case(ReplyStatus::Fail)]
+    #[case(ReplyStatus::Invalid)]
+    fn test_round_trip(#[case] status: ReplyStatus) {
+        assert_eq!(parse_reply_status(status.as_bytes()), Ok(status));
+    }
+
+    #[rstest]
+    #[case(MessageType::Reply, &["ok", "extra"], Some(Ok(ReplyStatus::Ok)))]
+    #[case(MessageType::Reply, &["fail"], Some(Ok(ReplyStatus::Fail)))]
+    #[case(
+        MessageType::Reply,
+        &["OK"],
+        Some(Err(ArgError::Invalid { index: 0, source: TypeError::UnknownStatus }))
+    )]
+    #[case(
+        MessageType::Reply,
+        &[],
+        Some(Err(ArgError::Missing { index: 0, count: 0 }))
+    )]
+    #[case(MessageType::Request, &["ok"], None)]
+    #[case(MessageType::Inform, &["ok"], None)]
+    fn test_reply_status(
+        #[case] mtype: MessageType,
+        #[case] arguments: &[&str],
+        #[case] expected: Option<Result<ReplyStatus, ArgError>>,
+    ) {
+        assert_eq!(message(mtype, arguments).reply_status(), expected);
+    }
+
+    #[test]
+    fn test_reply_to() {
+        let reply: Message<&[u8], &[u8]> = Message::reply_to(
+            b"sensor-value".as_slice(),
+            Some(5),
+            ReplyStatus::Fail,
+            vec![b"no such sensor".as_slice()],
+        )
+        .unwrap();
+        assert_eq!(
+            reply,
+            Message::<&[u8], &[u8]>::new(
+                MessageType::Reply,
+                b"sensor-value".as_slice(),
+                Some(5),
+                vec![b"fail".as_slice(), b"no such sensor"]
+            )
+        );
+        assert_eq!(reply.reply_status(), Some(Ok(ReplyStatus::Fail)));
+    }
+
+    #[test]
+    fn test_reply_helpers() {
+        let ok: OwnedMessage =
+            Message::reply_ok(b"help".to_vec(), Some(2), vec![b"3".to_vec()]).unwrap();
+        assert_eq!(ok.to_vec(), b"!help[2] ok 3\n");
+        let fail: OwnedMessage =
+            Message::reply_fail(b"help".to_vec(), None, b"no such request".to_vec()).unwrap();
+        assert_eq!(fail.to_vec(), b"!help fail no\\_such\\_request\n");
+        let inform: OwnedMessage =
+            Message::reply_inform(b"help".to_vec(), Some(2), vec![b"watchdog".to_vec()]).unwrap();
+        assert_eq!(inform.to_vec(), b"#help[2] watchdog\n");
+        let bad: Result<OwnedMessage, _> = Message::reply_inform(b"-".to_vec(), None, vec![]);
+        assert!(bad.is_err());
+    }
+
+    #[test]
+    fn test_reply_to_invalid_name() {
+        let reply: Result<OwnedMessage, _> =
+            Message::reply_to(b"bad name".to_vec(), None, ReplyStatus::Ok, vec![]);
+        assert_eq!(
+            reply,
+            Err(NameError::InvalidByte {
+                position: 3,
+                byte: b' '
+            })
+        );
+    }
+}
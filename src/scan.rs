@@ -0,0 +1,377 @@
+/* Copyright (c) 2024, National Research Foundation (SARAO)
+ *
+ * Licensed under the BSD 3-Clause License (the "License"); you may not use
+ * this file except in compliance with the License. You may obtain a copy
+ * of the License at
+ *
+ *   https://opensource.org/licenses/BSD-3-Clause
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Vectorised scanning of byte strings.
+//!
+//! Each function has a scalar implementation which is used for short inputs,
+//! for the tail of longer inputs, and on architectures without a vectorised
+//! implementation. On x86-64 SSE2 is always available and AVX2 is used if
+//! detected at runtime; on AArch64 NEON is always available.
+
+use crate::tables::{FastTable, ESCAPE_FLAG};
+
+/// The bytes for which [ESCAPE_FLAG] is set.
+const ESCAPE_BYTES: [u8; 7] = [b'\\', b' ', b'\0', b'\n', b'\r', 0x1b, b'\t'];
+
+/// Number of bytes that [find_escape] checks one at a time before switching
+/// to SIMD.
+const ESCAPE_SCALAR_PREFIX: usize = 8;
+
+/// Number of bytes that [find_rejected] checks one at a time before switching
+/// to SIMD. This is larger than [ESCAPE_SCALAR_PREFIX] because setting up the
+/// range checks costs more.
+const REJECT_SCALAR_PREFIX: usize = 32;
+
+/// Find the first byte of `data` that must be escaped, or `data.len()` if
+/// there are none.
+pub(crate) fn find_escape(data: &[u8]) -> usize {
+    // Escapes are often found within the first few bytes, in which case it
+    // is faster not to set up the vectorised scan.
+    let prefix = std::cmp::min(data.len(), ESCAPE_SCALAR_PREFIX);
+    let pos = find_escape_scalar(&data[..prefix]);
+    if pos < prefix {
+        return pos;
+    }
+    prefix + find_escape_simd(&data[prefix..])
+}
+
+/// Vectorised implementation of [find_escape].
+fn find_escape_simd(data: &[u8]) -> usize {
+    #[cfg(target_arch = "x86_64")]
+    {
+        if data.len() >= 32 && std::is_x86_feature_detected!("avx2") {
+            // SAFETY: we've just checked that the CPU supports AVX2
+            return unsafe { x86::find_escape_avx2(data) };
+        }
+        // SAFETY: SSE2 is part of the x86-64 baseline
+        unsafe { x86::find_escape_sse2(data) }
+    }
+    #[cfg(target_arch = "aarch64")]
+    {
+        // SAFETY: NEON is part of the AArch64 baseline
+        unsafe { neon::find_escape_neon(data) }
+    }
+    #[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+    {
+        find_escape_scalar(data)
+    }
+}
+
+/// Find the first byte of `data` not accepted by `table`, or `data.len()` if
+/// they are all accepted.
+pub(crate) fn find_rejected(table: &FastTable, data: &[u8]) -> usize {
+    // Most runs (message IDs, names and typical arguments) are short, so
+    // check the first few bytes before setting up the vectorised scan.
+    let prefix = std::cmp::min(data.len(), REJECT_SCALAR_PREFIX);
+    let pos = find_rejected_scalar(table, &data[..prefix]);
+    if pos < prefix {
+        return pos;
+    }
+    let data = &data[prefix..];
+    #[cfg(any(target_arch = "x86_64", target_arch = "aarch64"))]
+    if let Some(ranges) = table.reject_ranges {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if data.len() >= 32 && std::is_x86_feature_detected!("avx2") {
+                // SAFETY: we've just checked that the CPU supports AVX2
+                return prefix + unsafe { x86::find_rejected_avx2(table, ranges, data) };
+            } else if data.len() >= 16 {
+                // SAFETY: SSE2 is part of the x86-64 baseline
+                return prefix + unsafe { x86::find_rejected_sse2(table, ranges, data) };
+            }
+        }
+        #[cfg(target_arch = "aarch64")]
+        if data.len() >= 16 {
+            // SAFETY: NEON is part of the AArch64 baseline
+            return prefix + unsafe { neon::find_rejected_neon(table, ranges, data) };
+        }
+    }
+    prefix + find_rejected_scalar(table, data)
+}
+
+/// Scalar implementation of [find_rejected].
+fn find_rejected_scalar(table: &FastTable, data: &[u8]) -> usize {
+    data.iter()
+        .position(|&c| !table.accept[c])
+        .unwrap_or(data.len())
+}
+
+/// Scalar implementation of [find_escape].
+fn find_escape_scalar(data: &[u8]) -> usize {
+    data.iter()
+        .position(|&c| ESCAPE_FLAG[c])
+        .unwrap_or(data.len())
+}
+
+#[cfg(target_arch = "x86_64")]
+mod x86 {
+    use super::{find_escape_scalar, find_rejected_scalar, FastTable, ESCAPE_BYTES};
+    use std::arch::x86_64::*;
+
+    /// Find which bytes of `v` lie in the inclusive range `[lo, lo + span]`.
+    ///
+    /// Subtracting `lo` (with wraparound) maps the range to `[0, span]`, which
+    /// can then be tested with an unsigned minimum.
+    #[target_feature(enable = "sse2")]
+    unsafe fn in_range_sse2(v: __m128i, lo: __m128i, span: __m128i) -> __m128i {
+        let d = _mm_sub_epi8(v, lo);
+        _mm_cmpeq_epi8(_mm_min_epu8(d, span), d)
+    }
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn in_range_avx2(v: __m256i, lo: __m256i, span: __m256i) -> __m256i {
+        let d = _mm256_sub_epi8(v, lo);
+        _mm256_cmpeq_epi8(_mm256_min_epu8(d, span), d)
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn find_rejected_sse2(
+        table: &FastTable,
+        ranges: &[(u8, u8)],
+        data: &[u8],
+    ) -> usize {
+        const LANES: usize = 16;
+        let mut pos = 0;
+        while pos + LANES <= data.len() {
+            let v = _mm_loadu_si128(data.as_ptr().add(pos) as *const __m128i);
+            let mut hits = _mm_setzero_si128();
+            for &(lo, hi) in ranges.iter() {
+                let lo_v = _mm_set1_epi8(lo as i8);
+                let span_v = _mm_set1_epi8((hi - lo) as i8);
+                hits = _mm_or_si128(hits, in_range_sse2(v, lo_v, span_v));
+            }
+            let mask = _mm_movemask_epi8(hits) as u32;
+            if mask != 0 {
+                return pos + mask.trailing_zeros() as usize;
+            }
+            pos += LANES;
+        }
+        pos + find_rejected_scalar(table, &data[pos..])
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn find_rejected_avx2(
+        table: &FastTable,
+        ranges: &[(u8, u8)],
+        data: &[u8],
+    ) -> usize {
+        const LANES: usize = 32;
+        let mut pos = 0;
+        while pos + LANES <= data.len() {
+            let v = _mm256_loadu_si256(data.as_ptr().add(pos) as *const __m256i);
+            let mut hits = _mm256_setzero_si256();
+            for &(lo, hi) in ranges.iter() {
+                let lo_v = _mm256_set1_epi8(lo as i8);
+                let span_v = _mm256_set1_epi8((hi - lo) as i8);
+                hits = _mm256_or_si256(hits, in_range_avx2(v, lo_v, span_v));
+            }
+            let mask = _mm256_movemask_epi8(hits) as u32;
+            if mask != 0 {
+                return pos + mask.trailing_zeros() as usize;
+            }
+            pos += LANES;
+        }
+        pos + find_rejected_sse2(table, ranges, &data[pos..])
+    }
+
+    #[target_feature(enable = "sse2")]
+    pub(super) unsafe fn find_escape_sse2(data: &[u8]) -> usize {
+        const LANES: usize = 16;
+        let targets = ESCAPE_BYTES.map(|c| _mm_set1_epi8(c as i8));
+        let mut pos = 0;
+        while pos + LANES <= data.len() {
+            let v = _mm_loadu_si128(data.as_ptr().add(pos) as *const __m128i);
+            let mut hits = _mm_setzero_si128();
+            for target in targets.iter() {
+                hits = _mm_or_si128(hits, _mm_cmpeq_epi8(v, *target));
+            }
+            let mask = _mm_movemask_epi8(hits) as u32;
+            if mask != 0 {
+                return pos + mask.trailing_zeros() as usize;
+            }
+            pos += LANES;
+        }
+        pos + find_escape_scalar(&data[pos..])
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub(super) unsafe fn find_escape_avx2(data: &[u8]) -> usize {
+        const LANES: usize = 32;
+        let targets = ESCAPE_BYTES.map(|c| _mm256_set1_epi8(c as i8));
+        let mut pos = 0;
+        while pos + LANES <= data.len() {
+            let v = _mm256_loadu_si256(data.as_ptr().add(pos) as *const __m256i);
+            let mut hits = _mm256_setzero_si256();
+            for target in targets.iter() {
+                hits = _mm256_or_si256(hits, _mm256_cmpeq_epi8(v, *target));
+            }
+            let mask = _mm256_movemask_epi8(hits) as u32;
+            if mask != 0 {
+                return pos + mask.trailing_zeros() as usize;
+            }
+            pos += LANES;
+        }
+        pos + find_escape_sse2(&data[pos..])
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use super::{find_escape_scalar, find_rejected_scalar, FastTable, ESCAPE_BYTES};
+    use std::arch::aarch64::*;
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn find_rejected_neon(
+        table: &FastTable,
+        ranges: &[(u8, u8)],
+        data: &[u8],
+    ) -> usize {
+        const LANES: usize = 16;
+        let mut pos = 0;
+        while pos + LANES <= data.len() {
+            let v = vld1q_u8(data.as_ptr().add(pos));
+            let mut hits = vdupq_n_u8(0);
+            for &(lo, hi) in ranges.iter() {
+                // Subtracting lo (with wraparound) maps the range to [0, hi - lo]
+                let d = vsubq_u8(v, vdupq_n_u8(lo));
+                hits = vorrq_u8(hits, vcleq_u8(d, vdupq_n_u8(hi - lo)));
+            }
+            if vmaxvq_u8(hits) != 0 {
+                return pos + find_rejected_scalar(table, &data[pos..pos + LANES]);
+            }
+            pos += LANES;
+        }
+        pos + find_rejected_scalar(table, &data[pos..])
+    }
+
+    #[target_feature(enable = "neon")]
+    pub(super) unsafe fn find_escape_neon(data: &[u8]) -> usize {
+        const LANES: usize = 16;
+        let targets = ESCAPE_BYTES.map(|c| vdupq_n_u8(c));
+        let mut pos = 0;
+        while pos + LANES <= data.len() {
+            let v = vld1q_u8(data.as_ptr().add(pos));
+            let mut hits = vdupq_n_u8(0);
+            for target in targets.iter() {
+                hits = vorrq_u8(hits, vceqq_u8(v, *target));
+            }
+            if vmaxvq_u8(hits) != 0 {
+                // NEON has no movemask, so locate the byte within the chunk
+                // with the scalar code.
+                return pos + find_escape_scalar(&data[pos..pos + LANES]);
+            }
+            pos += LANES;
+        }
+        pos + find_escape_scalar(&data[pos..])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use proptest::prelude::*;
+
+    #[test]
+    fn escape_bytes_match_table() {
+        let expected: Vec<u8> = (0..=255u8).filter(|&c| ESCAPE_FLAG[c]).collect();
+        let mut actual = ESCAPE_BYTES.to_vec();
+        actual.sort();
+        assert_eq!(actual, expected);
+    }
+
+    /// Byte strings with long escape-free runs and the occasional escape
+    fn sparse_strategy() -> impl Strategy<Value = Vec<u8>> {
+        prop::collection::vec(
+            prop_oneof![
+                20 => b'a'..=b'z',
+                1 => prop::sample::select(ESCAPE_BYTES.to_vec()),
+                1 => any::<u8>(),
+            ],
+            0..200,
+        )
+    }
+
+    /// Fast tables with a mix of few and many rejected ranges
+    fn fast_table_strategy() -> impl Strategy<Value = FastTable> {
+        prop::collection::vec((any::<u8>(), 0..40u8), 0..12).prop_map(|chunks| {
+            let mut accept = enum_map::EnumMap::from_fn(|_| true);
+            for (lo, len) in chunks {
+                for ch in lo..=lo.saturating_add(len) {
+                    accept[ch] = false;
+                }
+            }
+            let mut ranges: Vec<(u8, u8)> = vec![];
+            for ch in 0..=255u8 {
+                if !accept[ch] {
+                    match ranges.last_mut() {
+                        Some(last) if last.1 + 1 == ch => last.1 = ch,
+                        _ => ranges.push((ch, ch)),
+                    }
+                }
+            }
+            FastTable {
+                accept,
+                reject_ranges: Some(Vec::leak(ranges)),
+            }
+        })
+    }
+
+    #[test]
+    fn parser_fast_tables_use_simd() {
+        use crate::tables::{lookup, PARSER_TABLE};
+        use katcp_codec_fsm::State;
+
+        // Names and arguments are the runs that benefit most
+        for (state, ch) in [(State::BeforeName, b'a'), (State::Argument, b'a')] {
+            let fast_table = lookup(&PARSER_TABLE, state, ch).fast_table.unwrap();
+            assert!(fast_table.reject_ranges.is_some());
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn find_rejected_matches_scalar(
+            table in fast_table_strategy(),
+            data in prop::collection::vec(any::<u8>(), 0..200)
+        ) {
+            let expected = find_rejected_scalar(&table, &data);
+            assert_eq!(find_rejected(&table, &data), expected);
+            #[cfg(target_arch = "x86_64")]
+            {
+                let ranges = table.reject_ranges.unwrap();
+                assert_eq!(unsafe { x86::find_rejected_sse2(&table, ranges, &data) }, expected);
+                if std::is_x86_feature_detected!("avx2") {
+                    assert_eq!(unsafe { x86::find_rejected_avx2(&table, ranges, &data) }, expected);
+                }
+            }
+        }
+
+        #[test]
+        fn find_escape_matches_scalar(data in sparse_strategy()) {
+            assert_eq!(find_escape(&data), find_escape_scalar(&data));
+        }
+
+        #[cfg(target_arch = "x86_64")]
+        #[test]
+        fn find_escape_x86_matches_scalar(data in sparse_strategy()) {
+            let expected = find_escape_scalar(&data);
+            assert_eq!(unsafe { x86::find_escape_sse2(&data) }, expected);
+            if std::is_x86_feature_detected!("avx2") {
+                assert_eq!(unsafe { x86::find_escape_avx2(&data) }, expected);
+            }
+        }
+    }
+}
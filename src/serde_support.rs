@@ -0,0 +1,183 @@
+/* Copyright (c) 2024, National Research Foundation (SARAO)
+ *
+ * Licensed under the BSD 3-Clause License (the "License"); you may not use
+ * this file except in compliance with the License. You may obtain a copy
+ * of the License at
+ *
+ *   https://opensource.org/licenses/BSD-3-Clause
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Serialization of [Message] with serde (only with the `serde` feature).
+//!
+//! A message is serialized as a struct with fields `mtype`, `name`, `mid`
+//! and `arguments`. The type uses the upper-case names of [MessageType]
+//! (matching the Python enum). The name must be valid UTF-8 and is
+//! serialized as a string. Arguments may be arbitrary bytes, so each is
+//! serialized with `serialize_bytes`. For formats without a native byte
+//! string type (such as JSON), this is an array of integers.
+
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Error as _, SerializeSeq, SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::message::{Message, MessageType};
+
+/// Wrapper to serialize a byte slice with `serialize_bytes`
+struct SerBytes<'a>(&'a [u8]);
+
+impl Serialize for SerBytes<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+/// Wrapper to serialize the arguments of a message
+struct SerArguments<'a, A: AsRef<[u8]>>(&'a [A]);
+
+impl<A: AsRef<[u8]>> Serialize for SerArguments<'_, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.len()))?;
+        for argument in self.0.iter() {
+            seq.serialize_element(&SerBytes(argument.as_ref()))?;
+        }
+        seq.end()
+    }
+}
+
+impl<N, A> Serialize for Message<N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let name = std::str::from_utf8(self.name.as_ref())
+            .map_err(|_| S::Error::custom("message name is not valid UTF-8"))?;
+        let mut state = serializer.serialize_struct("Message", 4)?;
+        state.serialize_field("mtype", &self.mtype)?;
+        state.serialize_field("name", name)?;
+        state.serialize_field("mid", &self.mid)?;
+        state.serialize_field("arguments", &SerArguments(&self.arguments))?;
+        state.end()
+    }
+}
+
+/// Byte string that can be deserialized from either bytes or a sequence
+struct DeBytes(Vec<u8>);
+
+impl<'de> Deserialize<'de> for DeBytes {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        impl<'de> Visitor<'de> for BytesVisitor {
+            type Value = DeBytes;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a byte string")
+            }
+
+            fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+                Ok(DeBytes(v.to_vec()))
+            }
+
+            fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(DeBytes(v))
+            }
+
+            fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+                let mut value = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(c) = seq.next_element()? {
+                    value.push(c);
+                }
+                Ok(DeBytes(value))
+            }
+        }
+
+        deserializer.deserialize_byte_buf(BytesVisitor)
+    }
+}
+
+/// Intermediate form used to deserialize a [Message]
+#[derive(Deserialize)]
+#[serde(rename = "Message")]
+struct DeMessage {
+    mtype: MessageType,
+    name: String,
+    mid: Option<u32>,
+    arguments: Vec<DeBytes>,
+}
+
+impl<'de, N, A> Deserialize<'de> for Message<N, A>
+where
+    N: AsRef<[u8]> + From<Vec<u8>>,
+    A: AsRef<[u8]> + From<Vec<u8>>,
+{
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let message = DeMessage::deserialize(deserializer)?;
+        Ok(Message::new(
+            message.mtype,
+            message.name.into_bytes(),
+            message.mid,
+            message
+                .arguments
+                .into_iter()
+                .map(|argument| argument.0.into())
+                .collect::<Vec<A>>(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use proptest::prelude::*;
+    use std::borrow::Cow;
+
+    use crate::message::OwnedMessage;
+    use crate::test::{arguments_strategy, mid_strategy, mtype_strategy, name_strategy};
+
+    #[test]
+    fn test_json() {
+        let message: Message<&[u8], &[u8]> = Message::new(
+            MessageType::Inform,
+            b"sensor-value".as_slice(),
+            Some(5),
+            vec![b"ok".as_slice(), b"\xff"],
+        );
+        let json = serde_json::to_string(&message).unwrap();
+        assert_eq!(
+            json,
+            r#"{"mtype":"INFORM","name":"sensor-value","mid":5,"arguments":[[111,107],[255]]}"#
+        );
+        let decoded: Message<Cow<[u8]>, Cow<[u8]>> = serde_json::from_str(&json).unwrap();
+        assert_eq!(decoded, message);
+    }
+
+    #[test]
+    fn test_invalid_name() {
+        let message: OwnedMessage = Message::new(MessageType::Request, b"\xff".to_vec(), None, []);
+        assert!(serde_json::to_string(&message).is_err());
+    }
+
+    proptest! {
+        #[test]
+        fn json_round_trip(
+            mtype in mtype_strategy(),
+            name in name_strategy(),
+            mid in mid_strategy(),
+            arguments in arguments_strategy()
+        ) {
+            let message: OwnedMessage = Message::new(mtype, name, mid, arguments);
+            let json = serde_json::to_vec(&message).unwrap();
+            let decoded: OwnedMessage = serde_json::from_slice(&json).unwrap();
+            assert_eq!(decoded, message);
+        }
+    }
+}
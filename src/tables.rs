@@ -13,6 +13,8 @@
  * limitations under the License.
  */
 
+#[cfg(feature = "compact-tables")]
+use enum_map::Enum;
 use enum_map::EnumMap;
 use katcp_codec_fsm::MessageType::*;
 use katcp_codec_fsm::{Action, State};
@@ -27,7 +29,88 @@ pub(crate) struct Entry {
     /// Whether to create a new argument before applying the action
     pub create_argument: bool,
     /// Following characters that can be merged into the action
-    pub fast_table: Option<&'static EnumMap<u8, bool>>,
+    pub fast_table: Option<&'static FastTable>,
+}
+
+/// Set of characters that can be merged into an action
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) struct FastTable {
+    /// Whether each character can be merged
+    pub accept: EnumMap<u8, bool>,
+    /// Inclusive ranges of characters that cannot be merged, if there are
+    /// few enough of them to check with SIMD
+    pub reject_ranges: Option<&'static [(u8, u8)]>,
+}
+
+/// Transition table for the whole state machine
+#[cfg(not(feature = "compact-tables"))]
+pub(crate) type ParserTable = EnumMap<State, EnumMap<u8, Entry>>;
+
+/// Transition table for the whole state machine
+#[cfg(feature = "compact-tables")]
+pub(crate) type ParserTable = EnumMap<State, EnumMap<u8, PackedEntry>>;
+
+/// An [Entry] packed into 16 bits, for the `compact-tables` feature.
+///
+/// From the least significant bit, this holds
+/// - 4 bits: the next state;
+/// - 5 bits: the index of the action in [ACTIONS];
+/// - 1 bit: whether to create a new argument;
+/// - 6 bits: the index in [FAST_TABLES] plus one, or zero for none.
+#[cfg(feature = "compact-tables")]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub(crate) struct PackedEntry(u16);
+
+#[cfg(feature = "compact-tables")]
+impl PackedEntry {
+    /// Decode the entry.
+    #[inline]
+    pub fn unpack(self) -> Entry {
+        let bits = self.0 as usize;
+        Entry {
+            action: ACTIONS[(bits >> 4) & 0x1f].clone(),
+            state: State::from_usize(bits & 0xf),
+            create_argument: bits & (1 << 9) != 0,
+            fast_table: match bits >> 10 {
+                0 => None,
+                index => Some(FAST_TABLES[index - 1]),
+            },
+        }
+    }
+}
+
+/// Look up the entry for a state and input character.
+#[cfg(not(feature = "compact-tables"))]
+#[inline]
+pub(crate) fn lookup(table: &'static ParserTable, state: State, ch: u8) -> &'static Entry {
+    &table[state][ch]
+}
+
+/// Look up the entry for a state and input character.
+#[cfg(feature = "compact-tables")]
+#[inline]
+pub(crate) fn lookup(table: &'static ParserTable, state: State, ch: u8) -> Entry {
+    table[state][ch].unpack()
 }
 
 include!(concat!(env!("OUT_DIR"), "/tables.rs"));
+
+#[cfg(all(test, feature = "compact-tables"))]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_packed_tables() {
+        for (packed, full) in [
+            (&PARSER_TABLE, &PARSER_TABLE_FULL),
+            (&PARSER_TABLE_V4, &PARSER_TABLE_V4_FULL),
+        ] {
+            for (state, row) in packed.iter() {
+                for (ch, entry) in row.iter() {
+                    assert!(entry.unpack() == full[state][ch], "{state:?} {ch}");
+                }
+            }
+        }
+        assert_eq!(std::mem::size_of::<PackedEntry>(), 2);
+    }
+}
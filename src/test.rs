@@ -16,9 +16,11 @@
 //! Tests that cut across modules
 
 use proptest::prelude::*;
+use std::borrow::Cow;
 
-use crate::message::{Message, MessageType};
-use crate::parse::Parser;
+use crate::format::escape_argument;
+use crate::message::{validate_name, Message, MessageType, OwnedMessage};
+use crate::parse::{unescape_argument, Parser};
 
 pub(crate) fn mtype_strategy() -> impl Strategy<Value = MessageType> {
     prop_oneof![
@@ -54,7 +56,7 @@ proptest! {
         arguments in arguments_strategy()
     )
     {
-        let message: Message<Vec<u8>, Vec<u8>> = Message::new(mtype, name, mid, arguments);
+        let message: OwnedMessage = Message::new(mtype, name, mid, arguments);
         let encoded = message.to_vec();
         let mut parser = Parser::new(1000000000);
         let decoded: Vec<_> = parser.append(&encoded).collect();
@@ -62,4 +64,261 @@ proptest! {
         let decoded = decoded[0].as_ref().unwrap();
         assert_eq!(*decoded, message);
     }
+
+    /// Test that [validate_name] accepts exactly the names the parser accepts
+    #[test]
+    fn validate_name_matches_parser(name in "[-A-Za-z0-9_.\u{e9}]{0,6}") {
+        let mut line = b"?".to_vec();
+        line.extend_from_slice(name.as_bytes());
+        line.push(b'\n');
+        let mut parser = Parser::new(1000);
+        let decoded: Vec<_> = parser.append(&line).collect();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(validate_name(name.as_bytes()).is_ok(), decoded[0].is_ok());
+    }
+
+    /// Test that [escape_argument] matches the message formatter and that
+    /// [unescape_argument] inverts it
+    #[test]
+    fn escape_argument_round_trip(raw in prop::collection::vec(0..255u8, 0..50)) {
+        let mut escaped = vec![];
+        escape_argument(&raw, &mut escaped);
+        let message: OwnedMessage = Message::new(MessageType::Request, b"x".to_vec(), None, [raw.clone()]);
+        assert_eq!(message.to_vec()[3..], [escaped.as_slice(), b"\n"].concat());
+        assert_eq!(unescape_argument(&escaped), Ok(raw));
+    }
+
+    /// Test that [Message::validate_line_length] agrees with the parser, and
+    /// [Message::fits] with [Message::write_size]
+    #[test]
+    fn validate_line_length_matches_parser(
+        mtype in mtype_strategy(),
+        name in name_strategy(),
+        mid in mid_strategy(),
+        arguments in prop::collection::vec(prop::collection::vec(0..255u8, 0..5), 0..5),
+        max_line_length in 1..40usize
+    )
+    {
+        let message: OwnedMessage = Message::new(mtype, name, mid, arguments);
+        let mut parser = Parser::new(max_line_length);
+        let encoded = message.to_vec();
+        let decoded: Vec<_> = parser.append(&encoded).collect();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(message.validate_line_length(max_line_length).is_ok(), decoded[0].is_ok());
+        assert_eq!(message.fits(max_line_length), message.write_size() <= max_line_length);
+    }
+
+    /// Test that the streaming, buffered and single-pass encoders agree
+    #[test]
+    fn write_to_matches_to_vec(
+        mtype in mtype_strategy(),
+        name in name_strategy(),
+        mid in mid_strategy(),
+        arguments in arguments_strategy()
+    )
+    {
+        let message: OwnedMessage = Message::new(mtype, name, mid, arguments);
+        let mut written = vec![];
+        let bytes = message.write_to(&mut written).unwrap();
+        assert_eq!(bytes, written.len());
+        assert_eq!(written, message.to_vec());
+        assert_eq!(message.to_vec_single_pass(), written);
+    }
+
+    /// Test that [MessageWriter](crate::format::MessageWriter) gives the
+    /// same output as [Message::to_vec], however the output is split
+    #[test]
+    fn writer_matches_to_vec(
+        mtype in mtype_strategy(),
+        name in name_strategy(),
+        mid in mid_strategy(),
+        arguments in arguments_strategy(),
+        chunk_size in 1..20usize
+    )
+    {
+        let message: OwnedMessage = Message::new(mtype, name, mid, arguments);
+        let mut writer = message.writer();
+        let mut written = vec![];
+        let mut buffer = vec![0u8; chunk_size];
+        loop {
+            let (n, done) = writer.write_some(&mut buffer);
+            written.extend_from_slice(&buffer[..n]);
+            if done {
+                break;
+            }
+            assert_eq!(n, chunk_size);
+        }
+        assert_eq!(written, message.to_vec());
+        assert_eq!(writer.write_some(&mut buffer), (0, true));
+    }
+}
+
+#[test]
+fn pairs() {
+    let message: OwnedMessage = Message::from_pairs(
+        MessageType::Inform,
+        b"sensor-value".to_vec(),
+        None,
+        [(b"name".as_slice(), b"x y".as_slice()), (b"status", b"")],
+    );
+    let encoded = message.to_vec();
+    assert_eq!(encoded, b"#sensor-value name x\\_y status \\@\n");
+    let mut parser = Parser::new(1000);
+    let decoded: Vec<_> = parser.append(&encoded).collect();
+    let pairs: Vec<_> = decoded[0].as_ref().unwrap().pairs().unwrap().collect();
+    assert_eq!(
+        pairs,
+        vec![(b"name".as_slice(), b"x y".as_slice()), (b"status", b"")]
+    );
+
+    assert_eq!(decoded[0].as_ref().unwrap().name_str(), Ok("sensor-value"));
+    assert_eq!(decoded[0].as_ref().unwrap().arg_count(), 4);
+    assert_eq!(decoded[0].as_ref().unwrap().arg(1), Some(b"x y".as_slice()));
+    assert_eq!(decoded[0].as_ref().unwrap().arg(4), None);
+
+    let invalid: OwnedMessage = Message::new(MessageType::Inform, b"\xff".to_vec(), None, []);
+    assert!(invalid.name_str().is_err());
+
+    let message: OwnedMessage = Message::new(
+        MessageType::Inform,
+        b"kv".to_vec(),
+        None,
+        [b"a=1".to_vec(), b"b=x=y".to_vec(), b"=".to_vec()],
+    );
+    assert_eq!(
+        message.arg_kv(1),
+        Some((b"b".as_slice(), b"x=y".as_slice()))
+    );
+    assert_eq!(message.arg_kv(3), None);
+    let kv: Vec<_> = message.kv_map().unwrap().collect();
+    assert_eq!(
+        kv,
+        vec![
+            (b"a".as_slice(), b"1".as_slice()),
+            (b"b", b"x=y"),
+            (b"", b"")
+        ]
+    );
+    let message: OwnedMessage = Message::new(
+        MessageType::Inform,
+        b"kv".to_vec(),
+        None,
+        [b"a=1".to_vec(), b"b".to_vec()],
+    );
+    assert_eq!(message.arg_kv(1), None);
+    assert!(message.kv_map().is_none());
+
+    // Odd number of arguments
+    let message: OwnedMessage =
+        Message::new(MessageType::Inform, b"odd".to_vec(), None, vec![vec![b'x']]);
+    assert!(message.pairs().is_none());
+}
+
+#[test]
+fn cow_message() {
+    let mut parser = Parser::new(1000);
+    let mut iter = parser.append(b"?help[2] a\\_b c\n");
+    let message = iter.next().unwrap().unwrap();
+    assert_eq!(message.as_cow(), message);
+    let owned: OwnedMessage = message.clone().into_owned();
+    assert_eq!(message, owned);
+    assert_eq!(owned.as_cow().into_owned(), owned);
+
+    // Unescaped arguments are borrowed from the input, and escaped ones are
+    // owned and moved out without copying
+    assert!(matches!(message.arguments[0], Cow::Owned(_)));
+    let ptr = message.arguments[0].as_ptr();
+    let arguments: Vec<_> = message.into_arguments().collect();
+    assert_eq!(arguments, vec![b"a b".to_vec(), b"c".to_vec()]);
+    assert_eq!(arguments[0].as_ptr(), ptr);
+}
+
+#[test]
+fn builder() {
+    use crate::message::MessageBuilder;
+
+    let message = MessageBuilder::new(MessageType::Request, "set")
+        .mid(3)
+        .arg_str("x y")
+        .arg_int(-42)
+        .arg_float(0.25)
+        .arg_bool(true)
+        .arg_bytes(b"\0".as_slice())
+        .build();
+    assert_eq!(message.to_vec(), b"?set[3] x\\_y -42 0.25 1 \\0\n");
+    assert_eq!(message.arg_f64(2), Ok(0.25));
+    assert_eq!(message.arg_bool(3), Ok(true));
+
+    let message = MessageBuilder::new(MessageType::Request, "old")
+        .mtype(MessageType::Reply)
+        .name("new")
+        .mid(None)
+        .build();
+    let expected: OwnedMessage = Message::new(MessageType::Reply, b"new".to_vec(), None, []);
+    assert_eq!(message, expected);
+}
+
+#[test]
+fn message_type_names() {
+    for (mtype, symbol, name) in [
+        (MessageType::Request, b'?', "request"),
+        (MessageType::Reply, b'!', "reply"),
+        (MessageType::Inform, b'#', "inform"),
+    ] {
+        assert_eq!(mtype.symbol(), symbol);
+        assert_eq!(MessageType::from_symbol(symbol), Some(mtype));
+        assert_eq!(mtype.to_string(), name);
+        assert_eq!(name.parse(), Ok(mtype));
+    }
+    assert_eq!(MessageType::from_symbol(b'$'), None);
+    assert_eq!(
+        "Request".parse::<MessageType>(),
+        Err(crate::message::UnknownMessageType)
+    );
+}
+
+#[test]
+fn validate_name_errors() {
+    use crate::message::NameError;
+
+    assert_eq!(validate_name(b"sensor-value"), Ok(()));
+    assert_eq!(validate_name(b""), Err(NameError::Empty));
+    assert_eq!(
+        validate_name(b"1st"),
+        Err(NameError::InvalidByte {
+            position: 0,
+            byte: b'1'
+        })
+    );
+    assert_eq!(
+        validate_name(b"sensor_value"),
+        Err(NameError::InvalidByte {
+            position: 6,
+            byte: b'_'
+        })
+    );
+}
+
+#[test]
+fn try_new() {
+    use crate::message::{MessageError, NameError, MAX_MESSAGE_ID};
+
+    for mid in [None, Some(1), Some(MAX_MESSAGE_ID)] {
+        let message = OwnedMessage::try_new(MessageType::Request, b"ok".to_vec(), mid, []).unwrap();
+        let mut parser = Parser::new(1000);
+        let encoded = message.to_vec();
+        let decoded: Vec<_> = parser.append(&encoded).collect();
+        assert_eq!(decoded.len(), 1);
+        assert_eq!(*decoded[0].as_ref().unwrap(), message);
+    }
+    for mid in [0, MAX_MESSAGE_ID + 1] {
+        assert_eq!(
+            OwnedMessage::try_new(MessageType::Request, b"bad".to_vec(), Some(mid), []),
+            Err(MessageError::InvalidId(mid))
+        );
+    }
+    assert_eq!(
+        OwnedMessage::try_new(MessageType::Request, b"".to_vec(), None, []),
+        Err(MessageError::Name(NameError::Empty))
+    );
 }
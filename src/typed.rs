@@ -0,0 +1,198 @@
+/* Copyright (c) 2024, National Research Foundation (SARAO)
+ *
+ * Licensed under the BSD 3-Clause License (the "License"); you may not use
+ * this file except in compliance with the License. You may obtain a copy
+ * of the License at
+ *
+ *   https://opensource.org/licenses/BSD-3-Clause
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Homogeneous typed arguments
+//!
+//! [FromKatcpArg] describes how to decode a single argument into a Rust
+//! type. It is implemented for the common types, and can be implemented
+//! downstream for application-specific types.
+
+use crate::message::Message;
+use crate::types::{parse_bool, parse_float, parse_int, parse_str, ArgError, TypeError};
+
+/// A type that can be decoded from a single message argument.
+pub trait FromKatcpArg: Sized {
+    /// Decode an argument.
+    fn from_katcp_arg(arg: &[u8]) -> Result<Self, TypeError>;
+}
+
+impl FromKatcpArg for i64 {
+    fn from_katcp_arg(arg: &[u8]) -> Result<Self, TypeError> {
+        parse_int(arg)
+    }
+}
+
+/// Implement [FromKatcpArg] for integer types narrower than [i64], giving
+/// [TypeError::InvalidInteger] if the value does not fit.
+macro_rules! impl_from_katcp_arg_int {
+    ($($t:ty),*) => {
+        $(
+            impl FromKatcpArg for $t {
+                fn from_katcp_arg(arg: &[u8]) -> Result<Self, TypeError> {
+                    parse_int(arg)?
+                        .try_into()
+                        .map_err(|_| TypeError::InvalidInteger)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_katcp_arg_int!(i8, i16, i32, u8, u16, u32);
+
+impl FromKatcpArg for u64 {
+    /// This accepts only decimal digits. It does not go through
+    /// [parse_int], which would reject values above [i64::MAX].
+    fn from_katcp_arg(arg: &[u8]) -> Result<Self, TypeError> {
+        if arg.is_empty() || !arg.iter().all(u8::is_ascii_digit) {
+            return Err(TypeError::InvalidInteger);
+        }
+        std::str::from_utf8(arg)
+            .map_err(|_| TypeError::InvalidInteger)?
+            .parse()
+            .map_err(|_| TypeError::InvalidInteger)
+    }
+}
+
+impl FromKatcpArg for f64 {
+    fn from_katcp_arg(arg: &[u8]) -> Result<Self, TypeError> {
+        parse_float(arg)
+    }
+}
+
+impl FromKatcpArg for bool {
+    fn from_katcp_arg(arg: &[u8]) -> Result<Self, TypeError> {
+        parse_bool(arg)
+    }
+}
+
+impl FromKatcpArg for Vec<u8> {
+    fn from_katcp_arg(arg: &[u8]) -> Result<Self, TypeError> {
+        Ok(arg.to_vec())
+    }
+}
+
+impl FromKatcpArg for String {
+    fn from_katcp_arg(arg: &[u8]) -> Result<Self, TypeError> {
+        parse_str(arg).map(str::to_owned)
+    }
+}
+
+impl<N, A> Message<N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    /// Decode argument `index` as a `T`.
+    pub fn arg_as<T: FromKatcpArg>(&self, index: usize) -> Result<T, ArgError> {
+        self.typed_arg(index, T::from_katcp_arg)
+    }
+
+    /// Decode every argument as a `T`.
+    ///
+    /// Decoding stops at the first argument that is not a valid `T`, and
+    /// the error gives its index.
+    pub fn typed_args<T: FromKatcpArg>(&self) -> Result<Vec<T>, ArgError> {
+        (0..self.arguments.len())
+            .map(|index| self.arg_as(index))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rstest::*;
+
+    use crate::message::{MessageType, OwnedMessage};
+
+    fn message(arguments: &[&[u8]]) -> OwnedMessage {
+        Message::new(
+            MessageType::Reply,
+            b"ports".to_vec(),
+            None,
+            arguments.iter().map(|arg| arg.to_vec()).collect::<Vec<_>>(),
+        )
+    }
+
+    #[rstest]
+    #[case(b"80", Ok(80))]
+    #[case(b"65535", Ok(65535))]
+    #[case(b"65536", Err(TypeError::InvalidInteger))]
+    #[case(b"-1", Err(TypeError::InvalidInteger))]
+    #[case(b"0x10", Err(TypeError::InvalidInteger))]
+    fn test_narrow_int(#[case] arg: &[u8], #[case] expected: Result<u16, TypeError>) {
+        assert_eq!(u16::from_katcp_arg(arg), expected);
+    }
+
+    #[rstest]
+    #[case(b"0", Ok(0))]
+    #[case(b"9223372036854775807", Ok(i64::MAX as u64))]
+    #[case(b"9223372036854775808", Ok(i64::MAX as u64 + 1))]
+    #[case(b"18446744073709551615", Ok(u64::MAX))]
+    #[case(b"18446744073709551616", Err(TypeError::InvalidInteger))]
+    #[case(b"-1", Err(TypeError::InvalidInteger))]
+    #[case(b"+1", Err(TypeError::InvalidInteger))]
+    #[case(b"", Err(TypeError::InvalidInteger))]
+    fn test_u64(#[case] arg: &[u8], #[case] expected: Result<u64, TypeError>) {
+        assert_eq!(u64::from_katcp_arg(arg), expected);
+    }
+
+    #[test]
+    fn test_arg_as_u64() {
+        let msg = message(&[b"18446744073709551615"]);
+        assert_eq!(msg.arg_as::<u64>(0), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn test_typed_args() {
+        let msg = message(&[b"80", b"443", b"8080"]);
+        assert_eq!(msg.typed_args::<u16>(), Ok(vec![80, 443, 8080]));
+        assert_eq!(msg.typed_args::<i64>(), Ok(vec![80, 443, 8080]));
+        assert_eq!(msg.typed_args::<f64>(), Ok(vec![80.0, 443.0, 8080.0]));
+        assert_eq!(
+            msg.typed_args::<String>(),
+            Ok(vec!["80".to_owned(), "443".to_owned(), "8080".to_owned()])
+        );
+        assert_eq!(
+            msg.typed_args::<u8>(),
+            Err(ArgError::Invalid {
+                index: 1,
+                source: TypeError::InvalidInteger
+            })
+        );
+
+        let msg = message(&[b"1", b"0", b"\xff"]);
+        assert_eq!(msg.arg_as::<bool>(1), Ok(false));
+        assert_eq!(
+            msg.typed_args::<Vec<u8>>(),
+            Ok(vec![b"1".to_vec(), b"0".to_vec(), b"\xff".to_vec()])
+        );
+        assert_eq!(
+            msg.typed_args::<bool>(),
+            Err(ArgError::Invalid {
+                index: 2,
+                source: TypeError::InvalidBoolean
+            })
+        );
+        assert_eq!(
+            msg.arg_as::<String>(3),
+            Err(ArgError::Missing { index: 3, count: 3 })
+        );
+
+        assert_eq!(message(&[]).typed_args::<f64>(), Ok(vec![]));
+    }
+}
@@ -0,0 +1,609 @@
+/* Copyright (c) 2024, National Research Foundation (SARAO)
+ *
+ * Licensed under the BSD 3-Clause License (the "License"); you may not use
+ * this file except in compliance with the License. You may obtain a copy
+ * of the License at
+ *
+ *   https://opensource.org/licenses/BSD-3-Clause
+ *
+ * Unless required by applicable law or agreed to in writing, software
+ * distributed under the License is distributed on an "AS IS" BASIS,
+ * WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+ * See the License for the specific language governing permissions and
+ * limitations under the License.
+ */
+
+//! Typed message arguments
+//!
+//! The katcp specification defines textual encodings for several data types.
+//! The functions in this module decode individual arguments, and [Schema]
+//! checks a whole message against a list of expected types.
+
+use std::collections::HashSet;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+use crate::message::Message;
+
+/// Error returned when an argument does not match its expected type.
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum TypeError {
+    #[error("invalid integer")]
+    InvalidInteger,
+    #[error("invalid float")]
+    InvalidFloat,
+    #[error("invalid boolean")]
+    InvalidBoolean,
+    #[error("timestamp out of range")]
+    TimestampOutOfRange,
+    #[error("invalid UTF-8")]
+    InvalidUtf8,
+    #[error("value is not in the discrete set")]
+    NotInDiscreteSet,
+    #[error("unknown reply status")]
+    UnknownStatus,
+}
+
+/// Decode an integer argument.
+///
+/// This accepts an optional minus sign followed by decimal digits.
+pub fn parse_int(arg: &[u8]) -> Result<i64, TypeError> {
+    let digits = arg.strip_prefix(b"-").unwrap_or(arg);
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return Err(TypeError::InvalidInteger);
+    }
+    std::str::from_utf8(arg)
+        .map_err(|_| TypeError::InvalidInteger)?
+        .parse()
+        .map_err(|_| TypeError::InvalidInteger)
+}
+
+/// Strip a leading run of ASCII digits, returning the number stripped and the remainder.
+fn strip_digits(arg: &[u8]) -> (usize, &[u8]) {
+    let n = arg.iter().take_while(|c| c.is_ascii_digit()).count();
+    (n, &arg[n..])
+}
+
+/// Whether `arg` is a decimal number with optional sign, fraction and exponent.
+fn is_decimal(arg: &[u8]) -> bool {
+    let rest = arg
+        .strip_prefix(b"-")
+        .or_else(|| arg.strip_prefix(b"+"))
+        .unwrap_or(arg);
+    let (int_digits, mut rest) = strip_digits(rest);
+    let mut frac_digits = 0;
+    if let Some(tail) = rest.strip_prefix(b".") {
+        (frac_digits, rest) = strip_digits(tail);
+    }
+    if int_digits + frac_digits == 0 {
+        return false;
+    }
+    if let Some(tail) = rest.strip_prefix(b"e").or_else(|| rest.strip_prefix(b"E")) {
+        let tail = tail
+            .strip_prefix(b"-")
+            .or_else(|| tail.strip_prefix(b"+"))
+            .unwrap_or(tail);
+        let exp_digits;
+        (exp_digits, rest) = strip_digits(tail);
+        if exp_digits == 0 {
+            return false;
+        }
+    }
+    rest.is_empty()
+}
+
+/// Decode a floating-point argument.
+///
+/// This accepts decimal numbers with an optional sign, fractional part and
+/// exponent. It also accepts the special values `nan`, `inf` and `-inf`
+/// (case-insensitive), as produced by Python's `%g` formatting.
+pub fn parse_float(arg: &[u8]) -> Result<f64, TypeError> {
+    let special = [
+        ("nan", f64::NAN),
+        ("inf", f64::INFINITY),
+        ("-inf", f64::NEG_INFINITY),
+    ];
+    for (token, value) in special {
+        if arg.eq_ignore_ascii_case(token.as_bytes()) {
+            return Ok(value);
+        }
+    }
+    if !is_decimal(arg) {
+        return Err(TypeError::InvalidFloat);
+    }
+    // from_utf8 can't fail after the validation above
+    std::str::from_utf8(arg)
+        .map_err(|_| TypeError::InvalidFloat)?
+        .parse()
+        .map_err(|_| TypeError::InvalidFloat)
+}
+
+/// Encode a floating-point argument.
+///
+/// Finite values use the shortest representation that decodes to the same
+/// value with [parse_float]. Non-finite values are written as `nan`, `inf`
+/// or `-inf`.
+pub fn format_float(value: f64) -> String {
+    if value.is_nan() {
+        "nan".to_owned()
+    } else if value.is_infinite() {
+        if value > 0.0 { "inf" } else { "-inf" }.to_owned()
+    } else {
+        format!("{value:?}")
+    }
+}
+
+/// Decode a boolean argument, which must be `0` or `1`.
+pub fn parse_bool(arg: &[u8]) -> Result<bool, TypeError> {
+    match arg {
+        b"0" => Ok(false),
+        b"1" => Ok(true),
+        _ => Err(TypeError::InvalidBoolean),
+    }
+}
+
+/// Encode a boolean argument.
+pub fn format_bool(value: bool) -> &'static [u8] {
+    if value {
+        b"1"
+    } else {
+        b"0"
+    }
+}
+
+/// Decode a timestamp argument, given as seconds since the Unix epoch.
+///
+/// The seconds are decoded with [parse_float]. Negative values give times
+/// before the epoch. Non-finite values, and values which are too large to
+/// represent as a [SystemTime], give [TypeError::TimestampOutOfRange].
+pub fn parse_timestamp(arg: &[u8]) -> Result<SystemTime, TypeError> {
+    let seconds = parse_float(arg)?;
+    let offset =
+        Duration::try_from_secs_f64(seconds.abs()).map_err(|_| TypeError::TimestampOutOfRange)?;
+    if seconds < 0.0 {
+        UNIX_EPOCH.checked_sub(offset)
+    } else {
+        UNIX_EPOCH.checked_add(offset)
+    }
+    .ok_or(TypeError::TimestampOutOfRange)
+}
+
+/// Encode a timestamp as seconds since the Unix epoch, rounded to the
+/// nearest microsecond.
+pub fn format_timestamp(value: SystemTime) -> String {
+    let (sign, offset) = match value.duration_since(UNIX_EPOCH) {
+        Ok(offset) => ("", offset),
+        Err(err) => ("-", err.duration()),
+    };
+    let micros = (offset.as_nanos() + 500) / 1000;
+    let sign = if micros == 0 { "" } else { sign };
+    format!("{sign}{}.{:06}", micros / 1_000_000, micros % 1_000_000)
+}
+
+/// Decode a string argument, which must be valid UTF-8.
+pub fn parse_str(arg: &[u8]) -> Result<&str, TypeError> {
+    std::str::from_utf8(arg).map_err(|_| TypeError::InvalidUtf8)
+}
+
+/// Set of allowed values for a discrete argument
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Discrete {
+    values: HashSet<String>,
+}
+
+impl Discrete {
+    /// Create a set from the allowed values.
+    ///
+    /// # Panics
+    ///
+    /// This will panic if any of the values is not ASCII.
+    pub fn new<S: Into<String>>(values: impl IntoIterator<Item = S>) -> Self {
+        let values: HashSet<String> = values.into_iter().map(|value| value.into()).collect();
+        assert!(
+            values.iter().all(|value| value.is_ascii()),
+            "discrete values must be ASCII"
+        );
+        Self { values }
+    }
+
+    /// Whether `value` is one of the allowed values.
+    pub fn contains(&self, value: &str) -> bool {
+        self.values.contains(value)
+    }
+}
+
+/// Decode a discrete argument, which must be one of the `allowed` values.
+pub fn parse_discrete<'a>(arg: &'a [u8], allowed: &Discrete) -> Result<&'a str, TypeError> {
+    // Allowed values are all ASCII, so anything else cannot match
+    let value = std::str::from_utf8(arg).map_err(|_| TypeError::NotInDiscreteSet)?;
+    if allowed.contains(value) {
+        Ok(value)
+    } else {
+        Err(TypeError::NotInDiscreteSet)
+    }
+}
+
+/// Encode a discrete argument, checking that it is one of the `allowed` values.
+pub fn format_discrete<'a>(value: &'a str, allowed: &Discrete) -> Result<&'a [u8], TypeError> {
+    if allowed.contains(value) {
+        Ok(value.as_bytes())
+    } else {
+        Err(TypeError::NotInDiscreteSet)
+    }
+}
+
+/// Error returned by the typed argument accessors, such as [Message::arg_f64].
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum ArgError {
+    #[error("argument {index} is missing (message has {count} arguments)")]
+    Missing { index: usize, count: usize },
+    #[error("argument {index}: {source}")]
+    Invalid { index: usize, source: TypeError },
+}
+
+impl<N, A> Message<N, A>
+where
+    N: AsRef<[u8]>,
+    A: AsRef<[u8]>,
+{
+    /// Decode an argument with a function from this module.
+    pub(crate) fn typed_arg<'a, T>(
+        &'a self,
+        index: usize,
+        decode: impl FnOnce(&'a [u8]) -> Result<T, TypeError>,
+    ) -> Result<T, ArgError> {
+        let arg = self.arguments.get(index).ok_or(ArgError::Missing {
+            index,
+            count: self.arguments.len(),
+        })?;
+        decode(arg.as_ref()).map_err(|source| ArgError::Invalid { index, source })
+    }
+
+    /// Decode argument `index` as a float (see [parse_float]).
+    pub fn arg_f64(&self, index: usize) -> Result<f64, ArgError> {
+        self.typed_arg(index, parse_float)
+    }
+
+    /// Decode argument `index` as a timestamp (see [parse_timestamp]).
+    pub fn arg_timestamp(&self, index: usize) -> Result<SystemTime, ArgError> {
+        self.typed_arg(index, parse_timestamp)
+    }
+
+    /// Decode argument `index` as a boolean (see [parse_bool]).
+    pub fn arg_bool(&self, index: usize) -> Result<bool, ArgError> {
+        self.typed_arg(index, parse_bool)
+    }
+}
+
+/// Type of a message argument
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Hash)]
+pub enum ArgType {
+    /// Any byte string
+    Bytes,
+    /// UTF-8 string (see [parse_str])
+    Str,
+    /// Integer (see [parse_int])
+    Int,
+    /// Floating-point number (see [parse_float])
+    Float,
+    /// Boolean (see [parse_bool])
+    Bool,
+}
+
+impl ArgType {
+    /// Check whether an argument is valid for this type.
+    pub fn check(&self, arg: &[u8]) -> Result<(), TypeError> {
+        match self {
+            ArgType::Bytes => Ok(()),
+            ArgType::Str => parse_str(arg).map(|_| ()),
+            ArgType::Int => parse_int(arg).map(|_| ()),
+            ArgType::Float => parse_float(arg).map(|_| ()),
+            ArgType::Bool => parse_bool(arg).map(|_| ()),
+        }
+    }
+}
+
+/// Error returned by [Schema::check].
+#[derive(Error, Clone, Debug, Eq, PartialEq)]
+pub enum SchemaError {
+    #[error("expected at least {expected} arguments, got {actual}")]
+    TooFewArguments { expected: usize, actual: usize },
+    #[error("expected at most {expected} arguments, got {actual}")]
+    TooManyArguments { expected: usize, actual: usize },
+    #[error("argument {index}: {source}")]
+    InvalidArgument { index: usize, source: TypeError },
+}
+
+/// Expected argument types for a message.
+///
+/// The message must have all the `required` arguments, followed by any
+/// prefix of the `optional` arguments. If `variadic` is set, any number of
+/// further arguments of that type may follow the optional arguments.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Schema {
+    /// Arguments that must be present
+    pub required: Vec<ArgType>,
+    /// Arguments that may be omitted from the end
+    pub optional: Vec<ArgType>,
+    /// Type of any further arguments
+    pub variadic: Option<ArgType>,
+}
+
+impl Schema {
+    /// Create a schema with only required arguments.
+    pub fn new(required: impl Into<Vec<ArgType>>) -> Self {
+        Self {
+            required: required.into(),
+            ..Default::default()
+        }
+    }
+
+    /// Check that the arguments of `message` match the schema.
+    pub fn check<N, A>(&self, message: &Message<N, A>) -> Result<(), SchemaError>
+    where
+        N: AsRef<[u8]>,
+        A: AsRef<[u8]>,
+    {
+        let actual = message.arguments.len();
+        if actual < self.required.len() {
+            return Err(SchemaError::TooFewArguments {
+                expected: self.required.len(),
+                actual,
+            });
+        }
+        let max_args = self.required.len() + self.optional.len();
+        if self.variadic.is_none() && actual > max_args {
+            return Err(SchemaError::TooManyArguments {
+                expected: max_args,
+                actual,
+            });
+        }
+        let types = self
+            .required
+            .iter()
+            .chain(self.optional.iter())
+            .chain(self.variadic.iter().cycle());
+        for (index, (arg_type, arg)) in types.zip(message.arguments.iter()).enumerate() {
+            arg_type
+                .check(arg.as_ref())
+                .map_err(|source| SchemaError::InvalidArgument { index, source })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rstest::*;
+
+    use crate::message::MessageType;
+
+    #[rstest]
+    #[case(b"0", Ok(0))]
+    #[case(b"-123", Ok(-123))]
+    #[case(b"9223372036854775807", Ok(i64::MAX))]
+    #[case(b"-9223372036854775808", Ok(i64::MIN))]
+    #[case(b"9223372036854775808", Err(TypeError::InvalidInteger))]
+    #[case(b"", Err(TypeError::InvalidInteger))]
+    #[case(b"-", Err(TypeError::InvalidInteger))]
+    #[case(b"+1", Err(TypeError::InvalidInteger))]
+    #[case(b"1.0", Err(TypeError::InvalidInteger))]
+    fn test_parse_int(#[case] arg: &[u8], #[case] expected: Result<i64, TypeError>) {
+        assert_eq!(parse_int(arg), expected);
+    }
+
+    #[rstest]
+    #[case(b"1.5", Ok(1.5))]
+    #[case(b"-2", Ok(-2.0))]
+    #[case(b"1e10", Ok(1e10))]
+    #[case(b"-0.0", Ok(-0.0))]
+    #[case(b"+.5E-3", Ok(0.5e-3))]
+    #[case(b"7.", Ok(7.0))]
+    #[case(b"inf", Ok(f64::INFINITY))]
+    #[case(b"-INF", Ok(f64::NEG_INFINITY))]
+    #[case(b"", Err(TypeError::InvalidFloat))]
+    #[case(b"1.2.3", Err(TypeError::InvalidFloat))]
+    #[case(b".", Err(TypeError::InvalidFloat))]
+    #[case(b"1e", Err(TypeError::InvalidFloat))]
+    #[case(b"e5", Err(TypeError::InvalidFloat))]
+    #[case(b"infinity", Err(TypeError::InvalidFloat))]
+    #[case(b"0x10", Err(TypeError::InvalidFloat))]
+    #[case(b" 1", Err(TypeError::InvalidFloat))]
+    #[case(b"\xFF", Err(TypeError::InvalidFloat))]
+    fn test_parse_float(#[case] arg: &[u8], #[case] expected: Result<f64, TypeError>) {
+        let value = parse_float(arg);
+        assert_eq!(value, expected);
+        // Distinguish -0.0 from 0.0
+        if let (Ok(value), Ok(expected)) = (value, expected) {
+            assert_eq!(value.is_sign_negative(), expected.is_sign_negative());
+        }
+    }
+
+    #[test]
+    fn test_parse_float_nan() {
+        assert!(parse_float(b"nan").unwrap().is_nan());
+        assert!(parse_float(b"NaN").unwrap().is_nan());
+    }
+
+    #[rstest]
+    #[case(b"0", Ok(UNIX_EPOCH))]
+    #[case(b"1700000000.25", Ok(UNIX_EPOCH + Duration::from_millis(1700000000250)))]
+    #[case(b"-1.5", Ok(UNIX_EPOCH - Duration::from_millis(1500)))]
+    #[case(b"1e30", Err(TypeError::TimestampOutOfRange))]
+    #[case(b"-1e30", Err(TypeError::TimestampOutOfRange))]
+    #[case(b"inf", Err(TypeError::TimestampOutOfRange))]
+    #[case(b"nan", Err(TypeError::TimestampOutOfRange))]
+    #[case(b"now", Err(TypeError::InvalidFloat))]
+    fn test_parse_timestamp(#[case] arg: &[u8], #[case] expected: Result<SystemTime, TypeError>) {
+        assert_eq!(parse_timestamp(arg), expected);
+    }
+
+    #[rstest]
+    #[case(0.0, "0.0")]
+    #[case(-1.5, "-1.5")]
+    #[case(0.1, "0.1")]
+    #[case(1e300, "1e300")]
+    #[case(-2.5e-10, "-2.5e-10")]
+    #[case(f64::INFINITY, "inf")]
+    #[case(f64::NEG_INFINITY, "-inf")]
+    #[case(f64::NAN, "nan")]
+    fn test_format_float(#[case] value: f64, #[case] expected: &str) {
+        let formatted = format_float(value);
+        assert_eq!(formatted, expected);
+        let parsed = parse_float(formatted.as_bytes()).unwrap();
+        assert!(parsed == value || (parsed.is_nan() && value.is_nan()));
+    }
+
+    #[rstest]
+    #[case(UNIX_EPOCH, "0.000000")]
+    #[case(UNIX_EPOCH + Duration::from_nanos(1_700_000_000_123_456_789), "1700000000.123457")]
+    #[case(UNIX_EPOCH - Duration::from_millis(1500), "-1.500000")]
+    #[case(UNIX_EPOCH - Duration::from_nanos(100), "0.000000")]
+    fn test_format_timestamp(#[case] value: SystemTime, #[case] expected: &str) {
+        assert_eq!(format_timestamp(value), expected);
+    }
+
+    #[test]
+    fn test_arg_timestamp() {
+        let message = message(&["1.5", "x"]);
+        assert_eq!(
+            message.arg_timestamp(0),
+            Ok(UNIX_EPOCH + Duration::from_millis(1500))
+        );
+        assert_eq!(
+            message.arg_timestamp(1),
+            Err(ArgError::Invalid {
+                index: 1,
+                source: TypeError::InvalidFloat
+            })
+        );
+    }
+
+    #[test]
+    fn test_arg_bool() {
+        let message = message(&["1", "0", "true", "false", "01", ""]);
+        assert_eq!(message.arg_bool(0), Ok(true));
+        assert_eq!(message.arg_bool(1), Ok(false));
+        for index in 2..6 {
+            assert_eq!(
+                message.arg_bool(index),
+                Err(ArgError::Invalid {
+                    index,
+                    source: TypeError::InvalidBoolean
+                })
+            );
+        }
+        assert_eq!(
+            message.arg_bool(6),
+            Err(ArgError::Missing { index: 6, count: 6 })
+        );
+        for value in [false, true] {
+            assert_eq!(parse_bool(format_bool(value)), Ok(value));
+        }
+    }
+
+    #[test]
+    fn test_arg_f64() {
+        let message = message(&["1.5", "1.2.3"]);
+        assert_eq!(message.arg_f64(0), Ok(1.5));
+        assert_eq!(
+            message.arg_f64(1),
+            Err(ArgError::Invalid {
+                index: 1,
+                source: TypeError::InvalidFloat
+            })
+        );
+        assert_eq!(
+            message.arg_f64(2),
+            Err(ArgError::Missing { index: 2, count: 2 })
+        );
+    }
+
+    #[fixture]
+    fn status() -> Discrete {
+        Discrete::new(["nominal", "warn", "error", "failure", "unknown"])
+    }
+
+    #[rstest]
+    #[case(b"nominal", Ok("nominal"))]
+    #[case(b"unknown", Ok("unknown"))]
+    #[case(b"Nominal", Err(TypeError::NotInDiscreteSet))]
+    #[case(b"", Err(TypeError::NotInDiscreteSet))]
+    #[case(b"warn\xFF", Err(TypeError::NotInDiscreteSet))]
+    fn test_parse_discrete(
+        status: Discrete,
+        #[case] arg: &[u8],
+        #[case] expected: Result<&str, TypeError>,
+    ) {
+        assert_eq!(parse_discrete(arg, &status), expected);
+    }
+
+    #[rstest]
+    fn test_format_discrete(status: Discrete) {
+        assert_eq!(format_discrete("warn", &status), Ok(b"warn".as_slice()));
+        assert_eq!(
+            format_discrete("bad", &status),
+            Err(TypeError::NotInDiscreteSet)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "discrete values must be ASCII")]
+    fn test_discrete_non_ascii() {
+        Discrete::new(["caf\u{e9}"]);
+    }
+
+    fn message(arguments: &[&'static str]) -> Message<&'static [u8], &'static [u8]> {
+        Message::new(
+            MessageType::Request,
+            b"test".as_slice(),
+            None,
+            arguments.iter().map(|x| x.as_bytes()).collect::<Vec<_>>(),
+        )
+    }
+
+    #[rstest]
+    #[case(&["3", "1.5", "hello"], Ok(()))]
+    #[case(&["3", "1.5"], Err(SchemaError::TooFewArguments { expected: 3, actual: 2 }))]
+    #[case(
+        &["3", "1.5", "hello", "extra"],
+        Err(SchemaError::TooManyArguments { expected: 3, actual: 4 }),
+    )]
+    #[case(
+        &["3", "x", "hello"],
+        Err(SchemaError::InvalidArgument { index: 1, source: TypeError::InvalidFloat }),
+    )]
+    fn test_schema_fixed(
+        #[case] arguments: &[&'static str],
+        #[case] expected: Result<(), SchemaError>,
+    ) {
+        let schema = Schema::new([ArgType::Int, ArgType::Float, ArgType::Str]);
+        assert_eq!(schema.check(&message(arguments)), expected);
+    }
+
+    #[rstest]
+    #[case(&["1"], Ok(()))]
+    #[case(&["1", "0"], Ok(()))]
+    #[case(&["1", "0", "7", "-8"], Ok(()))]
+    #[case(
+        &["1", "2"],
+        Err(SchemaError::InvalidArgument { index: 1, source: TypeError::InvalidBoolean }),
+    )]
+    #[case(
+        &["1", "0", "7", "x"],
+        Err(SchemaError::InvalidArgument { index: 3, source: TypeError::InvalidInteger }),
+    )]
+    fn test_schema_variadic(
+        #[case] arguments: &[&'static str],
+        #[case] expected: Result<(), SchemaError>,
+    ) {
+        let schema = Schema {
+            required: vec![ArgType::Bytes],
+            optional: vec![ArgType::Bool],
+            variadic: Some(ArgType::Int),
+        };
+        assert_eq!(schema.check(&message(arguments)), expected);
+    }
+}